@@ -0,0 +1,37 @@
+use std::error::Error;
+
+use magnetite::{Backend, Client, PeerId};
+use magnetite_libp2p::Libp2pBackend;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut builder = Libp2pBackend::builder().listen_on("/ip4/127.0.0.1/tcp/0".parse()?); //any port
+    if let Some(to_dial) = std::env::args().nth(1) {
+        builder = builder.dial(to_dial.parse()?);
+    }
+    if let Some(explicit) = std::env::args().nth(2) {
+        builder = builder.explicit_peer(explicit.parse()?);
+    }
+    let backend = builder.build()?;
+    println!("Local peer id: {}", backend.local_peer_id());
+
+    let client = Client::builder()
+        .want("configservice.address")
+        .want("configservice.port")
+        .want("configservice.user")
+        .build(backend);
+
+    let config = client.ready().await?;
+    println!("Got all wanted keys: {:?}", config);
+
+    // ask the explicit peer alone, the rest of the mesh doesn't see it
+    if let Some(explicit) = std::env::args().nth(2) {
+        let server = PeerId::new(explicit);
+        let port = client
+            .direct(server.clone())
+            .get("configservice.port")
+            .await?;
+        println!("Got configservice.port from {}: {}", server, port);
+    }
+    Ok(())
+}
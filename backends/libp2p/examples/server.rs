@@ -0,0 +1,56 @@
+use std::error::Error;
+use std::time::Duration;
+
+use magnetite::{Backend, Service};
+use magnetite_libp2p::Libp2pBackend;
+use magnetite_storage::{Crdt, Storage};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    // data directory given as the fourth argument, in memory otherwise;
+    // servers of the mesh converge on the same values
+    let coredb = match std::env::args().nth(4) {
+        Some(dir) => Storage::open(dir)?,
+        None => Storage::default(),
+    }
+    .crdt(Crdt::default());
+    // seed file given as the third argument, defaults otherwise; only used
+    // on first start, so writes survive restarts, and losing to the values
+    // the other servers already hold
+    if coredb.is_empty() {
+        match std::env::args().nth(3) {
+            Some(path) => coredb.seed_from_file(path)?,
+            None => coredb.seed(vec![
+                ("configservice.address", "localhost"),
+                ("configservice.port", "61250"),
+                ("configservice.user", "public"),
+            ])?,
+        }
+    }
+
+    let mut builder = Libp2pBackend::builder().listen_on("/ip4/127.0.0.1/tcp/61250".parse()?);
+    if let Some(to_dial) = std::env::args().nth(1) {
+        builder = builder.dial(to_dial.parse()?);
+    }
+    if let Some(explicit) = std::env::args().nth(2) {
+        builder = builder.explicit_peer(explicit.parse()?);
+    }
+    let backend = builder.build()?;
+    println!("Local peer id: {}", backend.local_peer_id());
+
+    // requests are only answered once synced with the mesh
+    let storage = coredb.clone();
+    tokio::spawn(async move {
+        while !storage.is_ready() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        println!("Storage ready, {} keys", storage.len());
+    });
+
+    Service::builder()
+        .handler(coredb)
+        .build(backend)
+        .run()
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,284 @@
+//! [libp2p] backend for `magnetite`, messages are exchanged over gossipsub.
+//!
+//! Messages for a single peer, see [`Backend::send`], go over a
+//! request-response protocol instead, straight to that peer, so the rest of
+//! the mesh neither carries nor sees them.
+//!
+//! [libp2p]: https://libp2p.io
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use libp2p::gossipsub::{
+    self, IdentTopic as Topic, MessageAuthenticity, PublishError, ValidationMode,
+};
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+use libp2p::{identity, noise, tcp, yamux, Multiaddr, StreamProtocol, Swarm};
+use serde::{Deserialize, Serialize};
+
+use magnetite::{Backend, BackendEvent, Error, PeerId, Result};
+
+/// Protocol of the messages sent to a single peer.
+pub const DIRECT_PROTOCOL: StreamProtocol = StreamProtocol::new("/magnetite/direct/1");
+
+/// Converts a libp2p peer id into the backend-agnostic one.
+pub fn peer_id(id: &libp2p::PeerId) -> PeerId {
+    PeerId::new(id.to_base58())
+}
+
+pub struct Builder {
+    keypair: Option<identity::Keypair>,
+    listen_on: Vec<Multiaddr>,
+    dial: Vec<Multiaddr>,
+    explicit_peers: Vec<libp2p::PeerId>,
+    heartbeat_interval: Duration,
+    direct_timeout: Duration,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            keypair: None,
+            listen_on: Vec::new(),
+            dial: Vec::new(),
+            explicit_peers: Vec::new(),
+            heartbeat_interval: Duration::from_secs(2),
+            direct_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Builder {
+    /// Identity of the node, a fresh ed25519 key is generated if unset.
+    pub fn keypair(mut self, keypair: identity::Keypair) -> Self {
+        self.keypair = Some(keypair);
+        self
+    }
+
+    pub fn listen_on(mut self, addr: Multiaddr) -> Self {
+        self.listen_on.push(addr);
+        self
+    }
+
+    pub fn dial(mut self, addr: Multiaddr) -> Self {
+        self.dial.push(addr);
+        self
+    }
+
+    /// Peer that messages are always forwarded to, regardless of the mesh.
+    pub fn explicit_peer(mut self, peer: libp2p::PeerId) -> Self {
+        self.explicit_peers.push(peer);
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// How long a message sent to a single peer may take to be acknowledged.
+    pub fn direct_timeout(mut self, timeout: Duration) -> Self {
+        self.direct_timeout = timeout;
+        self
+    }
+
+    /// Creates the swarm, must be called from within a tokio runtime.
+    pub fn build(self) -> Result<Libp2pBackend> {
+        let keypair = self
+            .keypair
+            .unwrap_or_else(identity::Keypair::generate_ed25519);
+        let heartbeat_interval = self.heartbeat_interval;
+        let direct_timeout = self.direct_timeout;
+
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .map_err(Error::backend)?
+            .with_behaviour(|key| {
+                let gossipsub_config = gossipsub::ConfigBuilder::default()
+                    .heartbeat_interval(heartbeat_interval)
+                    .validation_mode(ValidationMode::Permissive)
+                    .mesh_n(2)
+                    .mesh_n_low(2)
+                    .mesh_n_high(36)
+                    .gossip_lazy(2)
+                    .history_gossip(3)
+                    .mesh_outbound_min(1)
+                    .build()?;
+
+                // sign with my identity key
+                let gossipsub = gossipsub::Behaviour::new(
+                    MessageAuthenticity::Signed(key.clone()),
+                    gossipsub_config,
+                )?;
+                let direct = request_response::cbor::Behaviour::new(
+                    [(DIRECT_PROTOCOL, ProtocolSupport::Full)],
+                    request_response::Config::default().with_request_timeout(direct_timeout),
+                );
+                Ok(Behaviour { gossipsub, direct })
+            })
+            .map_err(Error::backend)?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+
+        for peer in &self.explicit_peers {
+            swarm.behaviour_mut().gossipsub.add_explicit_peer(peer);
+        }
+        for addr in self.listen_on {
+            swarm.listen_on(addr).map_err(Error::backend)?;
+        }
+        for addr in self.dial {
+            swarm.dial(addr).map_err(Error::backend)?;
+        }
+
+        let local_peer_id = peer_id(swarm.local_peer_id());
+        Ok(Libp2pBackend {
+            swarm,
+            local_peer_id,
+        })
+    }
+}
+
+/// A message for a single peer, acknowledged once received.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Direct {
+    /// Topic the peer receives the message on.
+    pub topic: String,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// The behaviours of the backend's swarm.
+#[derive(NetworkBehaviour)]
+pub struct Behaviour {
+    pub gossipsub: gossipsub::Behaviour,
+    pub direct: request_response::cbor::Behaviour<Direct, ()>,
+}
+
+/// Gossipsub based [`Backend`], every topic maps onto a gossipsub topic.
+pub struct Libp2pBackend {
+    swarm: Swarm<Behaviour>,
+    local_peer_id: PeerId,
+}
+
+impl Libp2pBackend {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    pub fn swarm(&self) -> &Swarm<Behaviour> {
+        &self.swarm
+    }
+
+    pub fn swarm_mut(&mut self) -> &mut Swarm<Behaviour> {
+        &mut self.swarm
+    }
+}
+
+#[async_trait]
+impl Backend for Libp2pBackend {
+    fn local_peer_id(&self) -> PeerId {
+        self.local_peer_id.clone()
+    }
+
+    fn peer_count(&self) -> usize {
+        self.swarm.behaviour().gossipsub.all_peers().count()
+    }
+
+    async fn subscribe(&mut self, topic: &str) -> Result<()> {
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&Topic::new(topic))
+            .map_err(Error::backend)?;
+        Ok(())
+    }
+
+    async fn publish(&mut self, topic: &str, data: Vec<u8>) -> Result<()> {
+        let published = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(Topic::new(topic), data);
+        match published {
+            Ok(_) => Ok(()),
+            Err(PublishError::InsufficientPeers) => Err(Error::InsufficientPeers),
+            Err(err) => Err(Error::backend(err)),
+        }
+    }
+
+    /// Sends `data` over the direct protocol, `peer` must be connected
+    /// already, no address is looked up. A message that can't be delivered
+    /// is reported as [`BackendEvent::Failed`] and dropped, requests are
+    /// retried.
+    async fn send(&mut self, peer: &PeerId, topic: &str, data: Vec<u8>) -> Result<()> {
+        let peer: libp2p::PeerId = peer.as_str().parse().map_err(Error::backend)?;
+        let direct = Direct {
+            topic: topic.to_owned(),
+            data,
+        };
+        self.swarm
+            .behaviour_mut()
+            .direct
+            .send_request(&peer, direct);
+        Ok(())
+    }
+
+    async fn next_event(&mut self) -> Option<BackendEvent> {
+        loop {
+            let event = match self.swarm.next().await? {
+                SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    propagation_source,
+                    message,
+                    ..
+                })) => BackendEvent::Message {
+                    source: peer_id(&message.source.unwrap_or(propagation_source)),
+                    topic: message.topic.into_string(),
+                    data: message.data,
+                },
+                SwarmEvent::Behaviour(BehaviourEvent::Direct(
+                    request_response::Event::Message {
+                        peer,
+                        message:
+                            request_response::Message::Request {
+                                request, channel, ..
+                            },
+                    },
+                )) => {
+                    // only tells the sender it arrived, answers are sent on their own
+                    let _ = self.swarm.behaviour_mut().direct.send_response(channel, ());
+                    BackendEvent::Message {
+                        source: peer_id(&peer),
+                        topic: request.topic,
+                        data: request.data,
+                    }
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::Direct(
+                    request_response::Event::OutboundFailure { peer, error, .. },
+                )) => BackendEvent::Failed {
+                    peer: peer_id(&peer),
+                    error: error.to_string(),
+                },
+                SwarmEvent::ConnectionEstablished { peer_id: id, .. } => {
+                    BackendEvent::PeerConnected(peer_id(&id))
+                }
+                SwarmEvent::ConnectionClosed {
+                    peer_id: id,
+                    num_established: 0,
+                    ..
+                } => BackendEvent::PeerDisconnected(peer_id(&id)),
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    BackendEvent::Listening(address.to_string())
+                }
+                _ => continue,
+            };
+            return Some(event);
+        }
+    }
+}
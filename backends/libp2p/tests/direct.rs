@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use magnetite::{Backend, BackendEvent, PeerId};
+use magnetite_libp2p::Libp2pBackend;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+/// Drives `backend` in the background, passing on its events.
+fn drive(mut backend: Libp2pBackend) -> mpsc::UnboundedReceiver<BackendEvent> {
+    let (events, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(event) = backend.next_event().await {
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Waits for the first event `wanted` picks something out of.
+async fn next<T>(
+    events: &mut mpsc::UnboundedReceiver<BackendEvent>,
+    mut wanted: impl FnMut(BackendEvent) -> Option<T>,
+) -> T {
+    let found = async {
+        loop {
+            let event = events.recv().await.expect("the backend stopped");
+            if let Some(found) = wanted(event) {
+                return found;
+            }
+        }
+    };
+    timeout(Duration::from_secs(10), found)
+        .await
+        .expect("the event never came")
+}
+
+#[tokio::test]
+async fn direct_messages_reach_their_peer() {
+    let server = Libp2pBackend::builder()
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .build()
+        .unwrap();
+    let server_id = server.local_peer_id();
+    let mut server_events = drive(server);
+    let address = next(&mut server_events, |event| match event {
+        BackendEvent::Listening(address) => Some(address),
+        _ => None,
+    })
+    .await;
+
+    let mut client = Libp2pBackend::builder()
+        .dial(address.parse().unwrap())
+        .build()
+        .unwrap();
+    let client_id = client.local_peer_id();
+    let connected = async {
+        loop {
+            if let Some(BackendEvent::PeerConnected(peer)) = client.next_event().await {
+                return peer;
+            }
+        }
+    };
+    let peer = timeout(Duration::from_secs(10), connected)
+        .await
+        .expect("the client never connected");
+    assert_eq!(peer, server_id);
+    client
+        .send(&server_id, "magnetite/default/direct", b"61250".to_vec())
+        .await
+        .unwrap();
+    let mut client_events = drive(client);
+
+    let (source, topic, data) = next(&mut server_events, |event| match event {
+        BackendEvent::Message {
+            source,
+            topic,
+            data,
+        } => Some((source, topic, data)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(source, client_id);
+    assert_eq!(topic, "magnetite/default/direct");
+    assert_eq!(data, b"61250");
+
+    // the acknowledgement is not a message
+    let quiet = timeout(Duration::from_millis(300), async {
+        loop {
+            if let Some(BackendEvent::Message { .. }) = client_events.recv().await {
+                return;
+            }
+        }
+    });
+    assert!(quiet.await.is_err());
+}
+
+#[tokio::test]
+async fn undeliverable_messages_are_reported() {
+    let mut client = Libp2pBackend::builder().build().unwrap();
+    let nobody = PeerId::new(libp2p::PeerId::random().to_base58());
+    client
+        .send(&nobody, "magnetite/default/direct", b"61250".to_vec())
+        .await
+        .unwrap();
+    let mut events = drive(client);
+
+    let peer = next(&mut events, |event| match event {
+        BackendEvent::Failed { peer, .. } => Some(peer),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer, nobody);
+}
@@ -0,0 +1,143 @@
+//! In-process backend for `magnetite`, meant for tests and examples.
+//!
+//! Every [`MemoryBackend`] joined to the same [`MemoryNetwork`] is connected
+//! to every other one, messages are delivered to all peers subscribed to the
+//! topic except the publisher, like gossipsub does.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use magnetite::handler::lock;
+use magnetite::{Backend, BackendEvent, Error, PeerId, Result};
+
+struct Peer {
+    subscriptions: HashSet<String>,
+    events: mpsc::UnboundedSender<BackendEvent>,
+}
+
+/// A set of peers that can all reach each other, cheap to clone.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    peers: Arc<Mutex<HashMap<PeerId, Peer>>>,
+    max_message_size: Option<usize>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        MemoryNetwork::default()
+    }
+
+    /// Fails to publish larger messages, like gossipsub does beyond its
+    /// `max_transmit_size`. Applies to the peers joined afterwards.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = Some(bytes);
+        self
+    }
+
+    /// Adds a peer to the network, panics if `name` is already taken.
+    pub fn join(&self, name: impl Into<PeerId>) -> MemoryBackend {
+        let local = name.into();
+        let (events, receiver) = mpsc::unbounded_channel();
+        let mut peers = self.peers();
+        assert!(
+            !peers.contains_key(&local),
+            "peer {} already joined the network",
+            local
+        );
+        for (id, peer) in peers.iter() {
+            let _ = peer.events.send(BackendEvent::PeerConnected(local.clone()));
+            let _ = events.send(BackendEvent::PeerConnected(id.clone()));
+        }
+        peers.insert(
+            local.clone(),
+            Peer {
+                subscriptions: HashSet::new(),
+                events,
+            },
+        );
+        MemoryBackend {
+            network: self.clone(),
+            local,
+            events: receiver,
+        }
+    }
+
+    fn peers(&self) -> MutexGuard<'_, HashMap<PeerId, Peer>> {
+        lock(&self.peers)
+    }
+}
+
+/// One peer of a [`MemoryNetwork`], leaves the network when dropped.
+pub struct MemoryBackend {
+    network: MemoryNetwork,
+    local: PeerId,
+    events: mpsc::UnboundedReceiver<BackendEvent>,
+}
+
+#[async_trait]
+impl Backend for MemoryBackend {
+    fn local_peer_id(&self) -> PeerId {
+        self.local.clone()
+    }
+
+    fn peer_count(&self) -> usize {
+        self.network.peers().len() - 1
+    }
+
+    async fn subscribe(&mut self, topic: &str) -> Result<()> {
+        if let Some(peer) = self.network.peers().get_mut(&self.local) {
+            peer.subscriptions.insert(topic.to_owned());
+        }
+        Ok(())
+    }
+
+    async fn publish(&mut self, topic: &str, data: Vec<u8>) -> Result<()> {
+        if let Some(max) = self
+            .network
+            .max_message_size
+            .filter(|max| data.len() > *max)
+        {
+            return Err(Error::backend(format!(
+                "message of {} bytes is over the {} bytes limit",
+                data.len(),
+                max
+            )));
+        }
+        let peers = self.network.peers();
+        let subscribers: Vec<&Peer> = peers
+            .iter()
+            .filter(|(id, peer)| **id != self.local && peer.subscriptions.contains(topic))
+            .map(|(_, peer)| peer)
+            .collect();
+        if subscribers.is_empty() {
+            return Err(Error::InsufficientPeers);
+        }
+        for peer in subscribers {
+            let _ = peer.events.send(BackendEvent::Message {
+                source: self.local.clone(),
+                topic: topic.to_owned(),
+                data: data.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn next_event(&mut self) -> Option<BackendEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for MemoryBackend {
+    fn drop(&mut self) {
+        let mut peers = self.network.peers();
+        peers.remove(&self.local);
+        for peer in peers.values() {
+            let _ = peer
+                .events
+                .send(BackendEvent::PeerDisconnected(self.local.clone()));
+        }
+    }
+}
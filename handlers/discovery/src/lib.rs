@@ -0,0 +1,266 @@
+//! Service discovery handler for `magnetite` services.
+//!
+//! [`Registry`] keeps the instances the services of the mesh announce, and
+//! answers the requests of [`magnetite::discovery`], resolving a service name
+//! to its live instances:
+//!
+//! ```no_run
+//! # async fn run<B: magnetite::Backend>(backend: B, client: magnetite::Client) -> magnetite::Result<()> {
+//! use futures::StreamExt;
+//! use magnetite::{Announcement, Service};
+//! use magnetite_discovery::Registry;
+//!
+//! Service::builder().handler(Registry::new()).build(backend).spawn();
+//!
+//! // on every instance of the config service, again before the ttl runs out
+//! client
+//!     .announce(&Announcement::new("configservice").address("/ip4/10.0.0.2/tcp/61250"))
+//!     .await?;
+//!
+//! // on its clients
+//! let mut instances = client.subscribe("configservice").await?;
+//! while let Some(instances) = instances.next().await {
+//!     println!("{} instances of the config service", instances.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every registry of a service sees every announcement, so running several
+//! of them keeps the list available while one restarts.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use magnetite::handler::lock;
+use magnetite::{
+    Announcement, Codec, Context, Handler, Instance, Message, MsgType, PeerId, Refusal, RequestId,
+    Result,
+};
+
+/// Keeps the instances of every service announced on the mesh.
+///
+/// Instances are dropped once their announcement's time to live passed, or
+/// when the peer that announced them disconnects. Clones share the same
+/// list, so a clone can be kept around to inspect it.
+#[derive(Clone, Default)]
+pub struct Registry {
+    state: Arc<Mutex<State>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Live instances of `service`, by peer.
+    pub fn instances(&self, service: &str) -> Vec<Instance> {
+        self.state().instances(service, Instant::now())
+    }
+
+    /// Names of the services with live instances.
+    pub fn services(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.state()
+            .services
+            .iter()
+            .filter(|(_, listed)| listed.values().any(|listed| listed.expires > now))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    services: BTreeMap<String, BTreeMap<PeerId, Listed>>,
+    subscribers: HashMap<RequestId, Subscriber>,
+    /// Services whose instances changed since their subscribers were told.
+    changed: BTreeSet<String>,
+}
+
+struct Listed {
+    instance: Instance,
+    expires: Instant,
+}
+
+struct Subscriber {
+    service: String,
+    /// Reply topic of the `Subscribe` request, updates are published there.
+    topic: String,
+}
+
+impl State {
+    fn instances(&self, service: &str, now: Instant) -> Vec<Instance> {
+        self.services
+            .get(service)
+            .into_iter()
+            .flat_map(BTreeMap::values)
+            .filter(|listed| listed.expires > now)
+            .map(|listed| listed.instance.clone())
+            .collect()
+    }
+
+    fn announce(&mut self, peer: PeerId, announcement: Announcement) -> Result<(), Refusal> {
+        if announcement.name.is_empty() {
+            return Err(Refusal::Invalid("services need a name".to_owned()));
+        }
+        let instance = Instance {
+            peer: peer.clone(),
+            addresses: announcement.addresses,
+            metadata: announcement.metadata,
+            health: announcement.health,
+        };
+        let now = Instant::now();
+        let expires = now
+            .checked_add(announcement.ttl)
+            .ok_or_else(|| Refusal::Invalid("time to live is too long".to_owned()))?;
+        let listed = self.services.entry(announcement.name.clone()).or_default();
+        // renewals only push the expiry back
+        let changed = listed
+            .get(&peer)
+            .is_none_or(|old| old.instance != instance || old.expires <= now);
+        listed.insert(peer, Listed { instance, expires });
+        if changed {
+            self.changed.insert(announcement.name);
+        }
+        Ok(())
+    }
+
+    fn withdraw(&mut self, service: &str, peer: &PeerId) -> bool {
+        let withdrawn = match self.services.get_mut(service) {
+            Some(listed) => listed.remove(peer).is_some(),
+            None => false,
+        };
+        if withdrawn {
+            self.changed.insert(service.to_owned());
+        }
+        withdrawn
+    }
+
+    /// Drops the instances whose announcement ran out.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        for (name, listed) in &mut self.services {
+            let before = listed.len();
+            listed.retain(|_, listed| listed.expires > now);
+            if listed.len() != before {
+                self.changed.insert(name.clone());
+            }
+        }
+        self.services.retain(|_, listed| !listed.is_empty());
+    }
+
+    fn disconnected(&mut self, peer: &PeerId) {
+        for (name, listed) in &mut self.services {
+            if listed.remove(peer).is_some() {
+                self.changed.insert(name.clone());
+            }
+        }
+        self.services.retain(|_, listed| !listed.is_empty());
+        self.subscribers.retain(|id, _| id.peer != *peer);
+    }
+
+    fn unwatch(&mut self, message: &Message, ctx: &Context) -> Result<()> {
+        let subscription: RequestId = ctx.format().decode(&message.payload)?;
+        // only the subscriber can end its subscription
+        if &subscription.peer == ctx.source() {
+            self.subscribers.remove(&subscription);
+        }
+        Ok(())
+    }
+
+    /// Publishes the instances of `service` to `subscribers`.
+    fn publish<'a>(
+        &self,
+        service: &str,
+        subscribers: impl Iterator<Item = (&'a RequestId, &'a Subscriber)>,
+        ctx: &mut Context,
+    ) -> Result<()> {
+        let payload = ctx
+            .topics()
+            .codec()
+            .encode(&self.instances(service, Instant::now()))?;
+        for (id, subscriber) in subscribers {
+            ctx.publish(
+                subscriber.topic.clone(),
+                Message {
+                    id: id.clone(),
+                    msgtype: MsgType::Notification,
+                    payload: payload.clone(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Tells the subscribers of every changed service.
+    fn notify(&mut self, ctx: &mut Context) -> Result<()> {
+        for service in std::mem::take(&mut self.changed) {
+            let subscribers = self
+                .subscribers
+                .iter()
+                .filter(|(_, subscriber)| subscriber.service == service);
+            self.publish(&service, subscribers, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl Handler for Registry {
+    fn handle(&mut self, message: &Message, ctx: &mut Context) -> Result<()> {
+        let mut state = self.state();
+        match message.msgtype {
+            // instances belong to the peer the transport got them from, a
+            // peer can't announce or withdraw them in another's name
+            MsgType::Announce => {
+                let reply = ctx
+                    .decode(message)
+                    .and_then(|announcement| state.announce(ctx.source().clone(), announcement));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Withdraw => {
+                let reply = ctx
+                    .decode::<String>(message)
+                    .map(|service| state.withdraw(&service, ctx.source()));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Resolve => {
+                let reply = ctx
+                    .decode::<String>(message)
+                    .map(|service| state.instances(&service, Instant::now()));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Subscribe => {
+                let service = ctx.decode::<String>(message);
+                ctx.reply_result(message, &service.as_ref().map(drop).map_err(Refusal::clone))?;
+                if let Ok(service) = service {
+                    let subscriber = Subscriber {
+                        topic: ctx.reply_topic(&message.id),
+                        service,
+                    };
+                    let subscribed = std::iter::once((&message.id, &subscriber));
+                    state.publish(&subscriber.service, subscribed, ctx)?;
+                    state.subscribers.insert(message.id.clone(), subscriber);
+                }
+            }
+            MsgType::Unwatch => state.unwatch(message, ctx)?,
+            _ => { /*not discovery*/ }
+        }
+        state.notify(ctx)
+    }
+
+    fn tick(&mut self, ctx: &mut Context) -> Result<()> {
+        let mut state = self.state();
+        state.expire();
+        state.notify(ctx)
+    }
+
+    fn disconnected(&mut self, peer: &PeerId) {
+        self.state().disconnected(peer);
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use magnetite::request::RequestIds;
+use magnetite::{
+    Announcement, Backend, BackendEvent, Client, Codec, Envelope, Error, Format, Health, Message,
+    MsgType, PeerId, Refusal, Service, Topics,
+};
+use magnetite_discovery::Registry;
+use magnetite_memory::MemoryNetwork;
+use tokio::time::{sleep, timeout};
+
+/// Serves `registry` ticking often enough to expire instances on time.
+fn serve(registry: &Registry) -> MemoryNetwork {
+    let network = MemoryNetwork::new();
+    Service::builder()
+        .tick(Duration::from_millis(20))
+        .handler(registry.clone())
+        .build(network.join("registry"))
+        .spawn();
+    network
+}
+
+fn config_service(address: &str) -> Announcement {
+    Announcement::new("configservice")
+        .address(address)
+        .metadata("user", "public")
+}
+
+#[tokio::test]
+async fn resolves_announced_instances() {
+    let registry = Registry::new();
+    let network = serve(&registry);
+    let first = Client::builder().build(network.join("config-a"));
+    let second = Client::builder().build(network.join("config-b"));
+    let client = Client::builder().build(network.join("client"));
+
+    first
+        .announce(&config_service("/ip4/10.0.0.1/tcp/61250"))
+        .await
+        .unwrap();
+    second
+        .announce(&config_service("/ip4/10.0.0.2/tcp/61250").health(Health::Critical))
+        .await
+        .unwrap();
+
+    let instances = client.resolve("configservice").await.unwrap();
+    assert_eq!(instances.len(), 2);
+    assert_eq!(instances[0].addresses, ["/ip4/10.0.0.1/tcp/61250"]);
+    assert_eq!(instances[0].metadata["user"], "public");
+    let serving: Vec<_> = instances.iter().filter(|i| i.is_serving()).collect();
+    assert_eq!(serving.len(), 1);
+
+    assert!(client.resolve("logservice").await.unwrap().is_empty());
+    assert_eq!(registry.services(), ["configservice"]);
+}
+
+#[tokio::test]
+async fn subscribers_follow_instances_coming_and_going() {
+    let registry = Registry::new();
+    let network = serve(&registry);
+    let service = Client::builder().build(network.join("config-a"));
+    let client = Client::builder().build(network.join("client"));
+
+    let mut instances = client.subscribe("configservice").await.unwrap();
+    assert!(instances.next().await.unwrap().is_empty());
+
+    let announcement = config_service("/ip4/10.0.0.1/tcp/61250");
+    service.announce(&announcement).await.unwrap();
+    let listed = instances.next().await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].health, Health::Passing);
+
+    // renewing changes nothing, a new health does
+    service.announce(&announcement).await.unwrap();
+    service
+        .announce(&announcement.health(Health::Warning))
+        .await
+        .unwrap();
+    assert_eq!(instances.next().await.unwrap()[0].health, Health::Warning);
+
+    assert!(service.withdraw("configservice").await.unwrap());
+    assert!(instances.next().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn instances_expire_unless_announced_again() {
+    let registry = Registry::new();
+    let network = serve(&registry);
+    let service = Client::builder().build(network.join("config-a"));
+
+    let announcement = config_service("/ip4/10.0.0.1/tcp/61250").ttl(Duration::from_millis(300));
+    service.announce(&announcement).await.unwrap();
+    for _ in 0..4 {
+        sleep(Duration::from_millis(100)).await;
+        service.announce(&announcement).await.unwrap();
+    }
+    assert_eq!(registry.instances("configservice").len(), 1);
+
+    sleep(Duration::from_millis(500)).await;
+    assert!(registry.instances("configservice").is_empty());
+    assert!(registry.services().is_empty());
+}
+
+#[tokio::test]
+async fn instances_leave_with_their_peer() {
+    let registry = Registry::new();
+    let network = serve(&registry);
+    let service = Client::builder().build(network.join("config-a"));
+    service
+        .announce(&config_service("/ip4/10.0.0.1/tcp/61250"))
+        .await
+        .unwrap();
+    assert_eq!(registry.instances("configservice").len(), 1);
+
+    drop(service);
+    let gone = async {
+        while !registry.instances("configservice").is_empty() {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), gone)
+        .await
+        .expect("instance outlived its peer");
+}
+
+#[tokio::test]
+async fn overlong_ttls_are_refused() {
+    let registry = Registry::new();
+    let network = serve(&registry);
+    let service = Client::builder().build(network.join("config-a"));
+
+    let announcement = config_service("/ip4/10.0.0.1/tcp/61250").ttl(Duration::MAX);
+    assert!(matches!(
+        service.announce(&announcement).await,
+        Err(Error::Refused(Refusal::Invalid(_)))
+    ));
+    assert!(registry.services().is_empty());
+}
+
+#[tokio::test]
+async fn peers_only_withdraw_their_own_instances() {
+    let registry = Registry::new();
+    let network = serve(&registry);
+    let service = Client::builder().build(network.join("config-a"));
+    let client = Client::builder().build(network.join("client"));
+    service
+        .announce(&config_service("/ip4/10.0.0.1/tcp/61250"))
+        .await
+        .unwrap();
+
+    // a withdraw claiming to come from the instance's peer
+    let mut mallory = network.join("mallory");
+    let message = Message {
+        id: RequestIds::new(PeerId::new("mallory")).next_id(),
+        msgtype: MsgType::Withdraw,
+        payload: Format::MessagePack.encode("configservice").unwrap(),
+    };
+    let forged = Envelope::new(PeerId::new("config-a"), message)
+        .encode(&Format::MessagePack)
+        .unwrap();
+    mallory
+        .publish(&Topics::default().request(), forged)
+        .await
+        .unwrap();
+
+    assert_eq!(client.resolve("configservice").await.unwrap().len(), 1);
+    assert!(client.withdraw("configservice").await.is_ok());
+    assert_eq!(registry.instances("configservice").len(), 1);
+}
+
+#[tokio::test]
+async fn only_the_subscriber_ends_its_subscription() {
+    let registry = Registry::new();
+    let network = serve(&registry);
+    let service = Client::builder().build(network.join("config-a"));
+    let client = Client::builder().build(network.join("client"));
+    let mut mallory = network.join("mallory");
+    let topic = Topics::default().request();
+    mallory.subscribe(&topic).await.unwrap();
+    let mut instances = client.subscribe("configservice").await.unwrap();
+    assert!(instances.next().await.unwrap().is_empty());
+
+    // mallory overhears the subscription
+    let overheard = async {
+        loop {
+            if let Some(BackendEvent::Message { data, .. }) = mallory.next_event().await {
+                let envelope = Envelope::decode(&Format::MessagePack, &data).unwrap();
+                if envelope.msgtype == MsgType::Subscribe {
+                    return envelope.correlation;
+                }
+            }
+        }
+    };
+    let subscription = timeout(Duration::from_secs(5), overheard).await.unwrap();
+
+    // and ends it in the name of the client
+    let message = Message {
+        id: RequestIds::new(PeerId::new("client")).next_id(),
+        msgtype: MsgType::Unwatch,
+        payload: Format::MessagePack.encode(&subscription).unwrap(),
+    };
+    let forged = Envelope::new(PeerId::new("client"), message)
+        .encode(&Format::MessagePack)
+        .unwrap();
+    mallory.publish(&topic, forged).await.unwrap();
+
+    service
+        .announce(&config_service("/ip4/10.0.0.1/tcp/61250"))
+        .await
+        .unwrap();
+    let listed = timeout(Duration::from_secs(5), instances.next())
+        .await
+        .unwrap();
+    assert_eq!(listed.unwrap().len(), 1);
+}
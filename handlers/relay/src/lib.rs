@@ -0,0 +1,180 @@
+//! Relay handler for `magnetite` services.
+//!
+//! [`Relay`] forwards the requests clients address to a single peer, see
+//! [`magnetite::relay`], so they reach it alone, even when the client can't
+//! talk to it directly, as long as both can talk to the relay:
+//!
+//! ```no_run
+//! # async fn run<B: magnetite::Backend>(backend: B, client: magnetite::Client) -> magnetite::Result<()> {
+//! use std::time::Duration;
+//!
+//! use magnetite::{PeerId, Route, Service};
+//! use magnetite_relay::{Limit, Relay};
+//!
+//! let relay = Relay::new().limit(Limit::default().max_messages(100, Duration::from_secs(1)));
+//! Service::builder().handler(relay).build(backend).spawn();
+//!
+//! // on a client, ask one config server only
+//! let server = client.routed(Route::to(PeerId::new("12D3KooW...")));
+//! let port = server.get("configservice.port").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use magnetite::handler::lock;
+use magnetite::{Context, Forward, Handler, Message, MsgType, PeerId, Refusal, Result};
+
+/// Limits of a route, the requests from one peer to another. Unlimited
+/// unless told otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limit {
+    max_bytes: Option<usize>,
+    max_messages: Option<(u32, Duration)>,
+}
+
+impl Limit {
+    /// Largest payload of a forwarded request.
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Number of requests forwarded every `period`, the rest is refused.
+    pub fn max_messages(mut self, messages: u32, period: Duration) -> Self {
+        self.max_messages = Some((messages, period));
+        self
+    }
+}
+
+/// Forwards requests to the peer they are addressed to.
+///
+/// Requests over their route's [`Limit`] are refused with a
+/// [`Refusal::Limited`]. Clones share the same limits and counters.
+#[derive(Clone, Default)]
+pub struct Relay {
+    state: Arc<Mutex<State>>,
+}
+
+impl Relay {
+    pub fn new() -> Self {
+        Relay::default()
+    }
+
+    /// Limit of every route without one of its own.
+    pub fn limit(self, limit: Limit) -> Self {
+        self.state().limit = limit;
+        self
+    }
+
+    /// Limit of the requests `from` sends to `to`.
+    pub fn route(self, from: PeerId, to: PeerId, limit: Limit) -> Self {
+        self.state().routes.insert((from, to), limit);
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    limit: Limit,
+    routes: HashMap<(PeerId, PeerId), Limit>,
+    /// Requests forwarded on every route in its current period.
+    windows: HashMap<(PeerId, PeerId), Window>,
+}
+
+struct Window {
+    started: Instant,
+    period: Duration,
+    forwarded: u32,
+}
+
+impl State {
+    /// Counts a request of `bytes` from `from` to `to`, if its route allows it.
+    fn admit(&mut self, from: &PeerId, to: &PeerId, bytes: usize) -> Result<(), Refusal> {
+        let route = (from.clone(), to.clone());
+        let limit = self.routes.get(&route).copied().unwrap_or(self.limit);
+        if let Some(max) = limit.max_bytes.filter(|max| bytes > *max) {
+            return Err(Refusal::Limited(format!(
+                "requests to {} are limited to {} bytes",
+                to, max
+            )));
+        }
+        let (max, period) = match limit.max_messages {
+            Some(max_messages) => max_messages,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let window = self.windows.entry(route).or_insert(Window {
+            started: now,
+            period,
+            forwarded: 0,
+        });
+        if now.duration_since(window.started) >= period {
+            window.started = now;
+            window.forwarded = 0;
+        }
+        if window.forwarded >= max {
+            return Err(Refusal::Limited(format!(
+                "requests from {} to {} are limited to {} every {:?}",
+                from, to, max, period
+            )));
+        }
+        window.forwarded += 1;
+        Ok(())
+    }
+}
+
+impl Handler for Relay {
+    fn handle(&mut self, message: &Message, ctx: &mut Context) -> Result<()> {
+        if message.msgtype != MsgType::Forward {
+            return Ok(());
+        }
+        let forward: Forward = match ctx.decode(message) {
+            Ok(forward) => forward,
+            Err(refusal) => return ctx.reply_result(message, &Err::<(), _>(refusal)),
+        };
+        if forward
+            .route
+            .relay
+            .as_ref()
+            .is_some_and(|relay| relay != ctx.local())
+        {
+            return Ok(());
+        }
+        // anyone can declare a sender, only the transport knows the peer
+        let admitted = if forward.envelope.sender != *ctx.source() {
+            Err(Refusal::Invalid(
+                "only the sender of a request can have it relayed".to_owned(),
+            ))
+        } else {
+            self.state().admit(
+                ctx.source(),
+                &forward.route.to,
+                forward.envelope.payload.len(),
+            )
+        };
+        match admitted {
+            Ok(()) => {
+                let topic = ctx.topics().direct(&forward.route.to);
+                ctx.forward(topic, forward.envelope);
+                Ok(())
+            }
+            Err(refusal) => ctx.reply_result(message, &Err::<(), _>(refusal)),
+        }
+    }
+
+    fn tick(&mut self, _ctx: &mut Context) -> Result<()> {
+        let now = Instant::now();
+        self.state()
+            .windows
+            .retain(|_, window| now.duration_since(window.started) < window.period);
+        Ok(())
+    }
+}
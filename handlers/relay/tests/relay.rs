@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use magnetite::request::RequestIds;
+use magnetite::{
+    Backend, Client, Codec, Envelope, Error, Format, Forward, Message, MsgType, PeerId, Refusal,
+    Route, Service, SetRequest, Topics,
+};
+use magnetite_memory::MemoryNetwork;
+use magnetite_relay::{Limit, Relay};
+use magnetite_storage::Storage;
+
+/// Two storage servers holding different ports, and `relay`.
+fn mesh(relay: Relay) -> MemoryNetwork {
+    let network = MemoryNetwork::new();
+    for (name, port) in [("server-a", "61250"), ("server-b", "61251")] {
+        let storage = Storage::default();
+        storage.insert("configservice.port", port).unwrap();
+        Service::builder()
+            .handler(storage)
+            .build(network.join(name))
+            .spawn();
+    }
+    Service::builder()
+        .handler(relay)
+        .build(network.join("relay"))
+        .spawn();
+    network
+}
+
+fn to(server: &str) -> Route {
+    Route::to(PeerId::new(server)).via(PeerId::new("relay"))
+}
+
+fn limited<T: std::fmt::Debug>(result: Result<T, Error>) -> bool {
+    matches!(result, Err(Error::Refused(Refusal::Limited(_))))
+}
+
+#[tokio::test]
+async fn routed_requests_reach_only_their_peer() {
+    let network = mesh(Relay::new());
+    let client = Client::builder().build(network.join("client"));
+
+    for _ in 0..3 {
+        let server = client.routed(to("server-b"));
+        assert_eq!(server.get("configservice.port").await.unwrap(), "61251");
+        let server = client.routed(to("server-a"));
+        assert_eq!(server.get("configservice.port").await.unwrap(), "61250");
+    }
+}
+
+#[tokio::test]
+async fn routes_over_their_rate_are_refused() {
+    let relay = Relay::new().limit(Limit::default().max_messages(2, Duration::from_secs(60)));
+    let network = mesh(relay);
+    let client = Client::builder().build(network.join("client"));
+
+    let server = client.routed(to("server-b"));
+    server.get("configservice.port").await.unwrap();
+    server.get("configservice.port").await.unwrap();
+    assert!(limited(server.get("configservice.port").await));
+
+    // other routes count on their own
+    let other = client.routed(to("server-a"));
+    assert_eq!(other.get("configservice.port").await.unwrap(), "61250");
+}
+
+#[tokio::test]
+async fn routes_limit_the_size_of_requests() {
+    let relay = Relay::new().route(
+        PeerId::new("client"),
+        PeerId::new("server-a"),
+        Limit::default().max_bytes(256),
+    );
+    let network = mesh(relay);
+    let client = Client::builder().build(network.join("client"));
+
+    let server = client.routed(to("server-a"));
+    let large = SetRequest::new("configservice.banner", "x".repeat(1000));
+    assert!(limited(server.send_set(large.clone()).await));
+    server.set("configservice.user", "admin").await.unwrap();
+
+    let unlimited = client.routed(to("server-b"));
+    unlimited.send_set(large).await.unwrap();
+}
+
+#[tokio::test]
+async fn peers_cant_spend_the_rate_of_others() {
+    let relay = Relay::new().limit(Limit::default().max_messages(2, Duration::from_secs(60)));
+    let network = mesh(relay);
+    let client = Client::builder().build(network.join("client"));
+    // other routes count on their own, this one waits for the relay
+    let other = client.routed(to("server-a"));
+    assert_eq!(other.get("configservice.port").await.unwrap(), "61250");
+
+    // requests claiming to come from the client, from another peer
+    let mut mallory = network.join("mallory");
+    let mut ids = RequestIds::new(PeerId::new("client"));
+    for _ in 0..2 {
+        let request = Message {
+            id: ids.next_id(),
+            msgtype: MsgType::Get,
+            payload: Format::MessagePack.encode("configservice.port").unwrap(),
+        };
+        let forward = Forward {
+            route: to("server-b"),
+            envelope: Envelope::new(PeerId::new("client"), request),
+        };
+        let message = Message {
+            id: ids.next_id(),
+            msgtype: MsgType::Forward,
+            payload: Format::MessagePack.encode(&forward).unwrap(),
+        };
+        let forged = Envelope::new(PeerId::new("client"), message)
+            .encode(&Format::MessagePack)
+            .unwrap();
+        mallory
+            .publish(&Topics::default().request(), forged)
+            .await
+            .unwrap();
+    }
+
+    let server = client.routed(to("server-b"));
+    assert_eq!(server.get("configservice.port").await.unwrap(), "61251");
+    assert_eq!(server.get("configservice.port").await.unwrap(), "61251");
+}
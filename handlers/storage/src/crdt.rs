@@ -0,0 +1,399 @@
+//! Eventually consistent storage, as a last-writer-wins map.
+//!
+//! Every server of a [`Storage::crdt`](crate::Storage::crdt) answers clients
+//! from its own copy and gossips its writes on
+//! [`Topics::replication`](magnetite::Topics::replication). Each key carries
+//! a [`Stamp`] of its last write, deletes included, and merging keeps the
+//! highest one, so servers that saw the same writes hold the same values
+//! whatever the order they saw them in.
+//!
+//! Stamps are wall-clock milliseconds, bumped past every stamp seen so far:
+//! a write always wins over the writes its server knew of, but of two
+//! concurrent writes the one from the fastest clock wins. Writes stamped
+//! more than [`MAX_SKEW`] ahead of the local clock are dropped, so a server
+//! with a clock far ahead can't win every key for good. Stamps live in
+//! memory, after a restart the stored values lose to any gossiped write, as
+//! do [seeded](crate::Storage::seed) values.
+//!
+//! A starting server sends a digest of the writes it has, the highest stamp
+//! it saw from every server, and stays silent until a peer answered with the
+//! writes it lacks or [`Crdt::sync_timeout`] passed.
+//!
+//! Deletes are kept as tombstones for [`Crdt::tombstone_ttl`], a server that
+//! did not hear of a delete by then brings the key back. Gossip is sent in
+//! parts of about [`MAX_CHUNK_BYTES`](crate::replication::MAX_CHUNK_BYTES).
+
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use magnetite::request::RequestIds;
+use magnetite::{Context, PeerId, Result, Value, Versioned};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::engine::{Batch, Op, StorageEngine};
+use crate::replication::{chunks, publish};
+
+/// How far ahead of the local clock a gossiped stamp may be.
+pub const MAX_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Settings of a CRDT storage, see [`Storage::crdt`](crate::Storage::crdt).
+#[derive(Clone, Debug)]
+pub struct Crdt {
+    gossip_every: Duration,
+    sync_timeout: Duration,
+    tombstone_ttl: Duration,
+}
+
+impl Default for Crdt {
+    fn default() -> Self {
+        Crdt {
+            gossip_every: Duration::from_secs(10),
+            sync_timeout: Duration::from_secs(3),
+            tombstone_ttl: Duration::from_secs(600),
+        }
+    }
+}
+
+impl Crdt {
+    /// How often the whole map is gossiped, so servers that missed a write
+    /// eventually get it.
+    pub fn gossip_every(mut self, interval: Duration) -> Self {
+        self.gossip_every = interval;
+        self
+    }
+
+    /// How long a starting server waits for its peers to send what it
+    /// missed, it answers clients afterwards even if nobody did.
+    pub fn sync_timeout(mut self, timeout: Duration) -> Self {
+        self.sync_timeout = timeout;
+        self
+    }
+
+    /// How long a delete is remembered, should span many
+    /// [`Crdt::gossip_every`]s so every server hears of it first.
+    pub fn tombstone_ttl(mut self, ttl: Duration) -> Self {
+        self.tombstone_ttl = ttl;
+        self
+    }
+}
+
+/// When and where a key was last written, higher stamps win.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct Stamp {
+    pub millis: u64,
+    /// Breaks ties between servers writing in the same millisecond.
+    pub node: PeerId,
+}
+
+/// What the servers tell each other.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) enum Gossip {
+    Updates(Vec<Update>),
+    /// Highest stamp the sender saw from every server.
+    Digest(HashMap<PeerId, u64>),
+    /// Answer to a digest, the writes `to` lacked, `done` on its last part.
+    Delta {
+        to: PeerId,
+        updates: Vec<Update>,
+        done: bool,
+    },
+}
+
+/// Last write of a key, `None` for a delete.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Update {
+    key: String,
+    value: Option<Value>,
+    stamp: Stamp,
+}
+
+impl Update {
+    /// Roughly the size of the update once encoded.
+    fn size(&self) -> usize {
+        let value = self
+            .value
+            .as_ref()
+            .map_or(0, |value| value.as_bytes().len());
+        self.key.len() + value + self.stamp.node.as_str().len() + 32
+    }
+}
+
+pub(crate) struct LwwMap {
+    config: Crdt,
+    ids: Option<RequestIds>,
+    /// Stamp of every key written since startup, deleted ones included.
+    stamps: HashMap<String, Stamp>,
+    /// Highest stamp seen from every server.
+    seen: HashMap<PeerId, u64>,
+    clock: u64,
+    ready: bool,
+    sync_started: Option<Instant>,
+    last_gossip: Option<Instant>,
+    outbox: Vec<Gossip>,
+}
+
+impl LwwMap {
+    pub fn new(config: Crdt) -> Self {
+        LwwMap {
+            config,
+            ids: None,
+            stamps: HashMap::new(),
+            seen: HashMap::new(),
+            clock: 0,
+            ready: false,
+            sync_started: None,
+            last_gossip: None,
+            outbox: Vec::new(),
+        }
+    }
+
+    fn local(&self) -> PeerId {
+        // writes made before the service started are stamped once it does
+        self.ids
+            .as_ref()
+            .map_or_else(|| PeerId::new(""), |ids| ids.peer().clone())
+    }
+
+    /// Whether the starting sync is over.
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Learns the local peer id, the first time a handler call sees it.
+    pub fn attach(&mut self, ctx: &Context) {
+        if self.ids.is_none() {
+            self.ids = Some(RequestIds::new(ctx.local().clone()));
+            let local = self.local();
+            for stamp in self.stamps.values_mut() {
+                if stamp.millis > 0 {
+                    stamp.node = local.clone();
+                }
+            }
+            if let Some(seen) = self.seen.remove(&PeerId::new("")) {
+                self.seen.insert(local, seen);
+            }
+        }
+    }
+
+    fn stamp(&mut self) -> Stamp {
+        self.clock = now_millis().max(self.clock.saturating_add(1));
+        self.seen.insert(self.local(), self.clock);
+        Stamp {
+            millis: self.clock,
+            node: self.local(),
+        }
+    }
+
+    /// Stamps the writes of a local `batch` and queues them for gossip.
+    pub fn committed(&mut self, batch: &Batch) {
+        let stamp = self.stamp();
+        let updates = batch
+            .ops
+            .iter()
+            .map(|op| {
+                let (key, value) = match op {
+                    Op::Put { key, value } => (key.clone(), Some(value.value.clone())),
+                    Op::Remove { key } => (key.clone(), None),
+                };
+                self.stamps.insert(key.clone(), stamp.clone());
+                Update {
+                    key,
+                    value,
+                    stamp: stamp.clone(),
+                }
+            })
+            .collect();
+        self.gossip(updates);
+    }
+
+    /// Queues `updates` in as many parts as needed.
+    fn gossip(&mut self, updates: Vec<Update>) {
+        for part in chunks(updates, Update::size) {
+            self.outbox.push(Gossip::Updates(part));
+        }
+    }
+
+    /// Takes seeded values as unstamped, they lose to any write.
+    pub fn seeded(&mut self, batch: &Batch) {
+        for op in &batch.ops {
+            match op {
+                Op::Put { key, .. } | Op::Remove { key } => self.stamps.remove(key),
+            };
+        }
+    }
+
+    /// Syncs when starting, forgets old tombstones, and gossips the whole
+    /// map when it is due.
+    pub fn tick(&mut self, engine: &dyn StorageEngine) {
+        if !self.ready {
+            match self.sync_started {
+                None => {
+                    self.sync_started = Some(Instant::now());
+                    self.outbox.push(Gossip::Digest(self.seen.clone()));
+                }
+                Some(started) if started.elapsed() >= self.config.sync_timeout => {
+                    info!("no peer answered the sync, ready on our own");
+                    self.ready = true;
+                }
+                Some(_) => {}
+            }
+        }
+
+        let due = self
+            .last_gossip
+            .is_none_or(|last| last.elapsed() >= self.config.gossip_every);
+        if !due {
+            return;
+        }
+        self.last_gossip = Some(Instant::now());
+
+        let ttl = self.config.tombstone_ttl.as_millis() as u64;
+        let oldest = now_millis().saturating_sub(ttl);
+        self.stamps
+            .retain(|key, stamp| stamp.millis >= oldest || engine.get(key).is_some());
+
+        let updates = self.updates(engine, |_| true);
+        self.gossip(updates);
+    }
+
+    /// Every stored value and tombstone whose stamp passes `wanted`.
+    fn updates(&self, engine: &dyn StorageEngine, wanted: impl Fn(&Stamp) -> bool) -> Vec<Update> {
+        // values stored before startup lose to any stamped write
+        let unstamped = Stamp {
+            millis: 0,
+            node: self.local(),
+        };
+        let mut updates: Vec<Update> = engine
+            .range(Bound::Unbounded)
+            .map(|(key, got)| Update {
+                stamp: self.stamps.get(&key).unwrap_or(&unstamped).clone(),
+                key,
+                value: Some(got.value),
+            })
+            .filter(|update| wanted(&update.stamp))
+            .collect();
+        updates.extend(
+            self.stamps
+                .iter()
+                .filter(|(key, stamp)| engine.get(key).is_none() && wanted(stamp))
+                .map(|(key, stamp)| Update {
+                    key: key.clone(),
+                    value: None,
+                    stamp: stamp.clone(),
+                }),
+        );
+        updates
+    }
+
+    /// Handles gossip from `from`.
+    pub fn receive(&mut self, from: PeerId, gossip: Gossip, engine: &mut dyn StorageEngine) {
+        match gossip {
+            Gossip::Updates(updates) => self.merge(updates, engine),
+            Gossip::Digest(seen) => {
+                // a server still syncing may lack what it is asked for
+                if self.ready {
+                    let updates = self.updates(&*engine, |stamp| {
+                        seen.get(&stamp.node)
+                            .is_none_or(|seen| stamp.millis > *seen)
+                    });
+                    let parts = chunks(updates, Update::size);
+                    let last = parts.len() - 1;
+                    for (part, updates) in parts.into_iter().enumerate() {
+                        self.outbox.push(Gossip::Delta {
+                            to: from.clone(),
+                            updates,
+                            done: part == last,
+                        });
+                    }
+                }
+            }
+            Gossip::Delta { to, updates, done } => {
+                self.merge(updates, engine);
+                if done && !self.ready && Some(&to) == self.ids.as_ref().map(RequestIds::peer) {
+                    info!(
+                        "synced with {}, ready at revision {}",
+                        from,
+                        engine.revision()
+                    );
+                    self.ready = true;
+                }
+            }
+        }
+    }
+
+    /// Merges gossiped `updates`, keeping the last write of every key.
+    fn merge(&mut self, updates: Vec<Update>, engine: &mut dyn StorageEngine) {
+        let unstamped = Stamp {
+            millis: 0,
+            node: self.local(),
+        };
+        let horizon = now_millis().saturating_add(MAX_SKEW.as_millis() as u64);
+        let mut revision = engine.revision();
+        let mut ops = Vec::new();
+        for update in updates {
+            if update.stamp.millis > horizon {
+                warn!(
+                    "dropped a write of {} stamped {}ms ahead by {}",
+                    update.key,
+                    update.stamp.millis - now_millis(),
+                    update.stamp.node
+                );
+                continue;
+            }
+            self.clock = self.clock.max(update.stamp.millis);
+            if update.stamp.millis > 0 {
+                let seen = self.seen.entry(update.stamp.node.clone()).or_default();
+                *seen = (*seen).max(update.stamp.millis);
+            }
+            let newer = match self.stamps.get(&update.key) {
+                Some(stamp) => update.stamp > *stamp,
+                None if engine.get(&update.key).is_some() => update.stamp > unstamped,
+                None => true,
+            };
+            if !newer {
+                continue;
+            }
+            let current = engine.get(&update.key).map(|got| got.value);
+            self.stamps.insert(update.key.clone(), update.stamp);
+            if current == update.value {
+                continue;
+            }
+            ops.push(match update.value {
+                Some(value) => {
+                    revision += 1;
+                    Op::Put {
+                        key: update.key,
+                        value: Versioned {
+                            value,
+                            version: revision,
+                        },
+                    }
+                }
+                None => Op::Remove { key: update.key },
+            });
+        }
+        if ops.is_empty() {
+            return;
+        }
+        if let Err(err) = engine.apply(Batch { revision, ops }) {
+            warn!("failed to merge gossiped writes: {}", err);
+        }
+    }
+
+    /// Publishes the queued gossip on the replication topic.
+    pub fn flush(&mut self, ctx: &mut Context) -> Result<()> {
+        match &mut self.ids {
+            Some(ids) => publish(ctx, ids, self.outbox.drain(..)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,103 @@
+//! Where a [`Storage`](crate::Storage) keeps its values.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Bound;
+
+use magnetite::Versioned;
+use serde::{Deserialize, Serialize};
+
+/// One change to the stored values.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Op {
+    Put { key: String, value: Versioned },
+    Remove { key: String },
+}
+
+/// Changes applied together, either all of them or none.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Batch {
+    /// Highest version handed out once the batch is applied, the engine
+    /// takes it as is, so a batch catching up with a leader can lower it.
+    pub revision: u64,
+    pub ops: Vec<Op>,
+}
+
+/// Key/value store underneath a [`Storage`](crate::Storage).
+///
+/// Engines only keep what they are given, versions are handed out by the
+/// storage. Applying a batch again must leave the engine unchanged, so a
+/// durable engine can safely replay batches after a crash.
+pub trait StorageEngine: Send + 'static {
+    /// Highest version handed out so far, including deleted values.
+    fn revision(&self) -> u64;
+
+    fn get(&self, key: &str) -> Option<Versioned>;
+
+    /// Entries from `start` on, in key order.
+    fn range(&self, start: Bound<&str>) -> Box<dyn Iterator<Item = (String, Versioned)> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Applies `batch` atomically, once this returns it is as durable as the
+    /// engine gets.
+    fn apply(&mut self, batch: Batch) -> io::Result<()>;
+}
+
+/// Keeps everything in memory, nothing survives a restart.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MemoryEngine {
+    revision: u64,
+    entries: BTreeMap<String, Versioned>,
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        MemoryEngine::default()
+    }
+
+    pub(crate) fn apply_in_memory(&mut self, batch: Batch) {
+        self.revision = batch.revision;
+        for op in batch.ops {
+            match op {
+                Op::Put { key, value } => {
+                    self.entries.insert(key, value);
+                }
+                Op::Remove { key } => {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    fn get(&self, key: &str) -> Option<Versioned> {
+        self.entries.get(key).cloned()
+    }
+
+    fn range(&self, start: Bound<&str>) -> Box<dyn Iterator<Item = (String, Versioned)> + '_> {
+        Box::new(
+            self.entries
+                .range::<str, _>((start, Bound::Unbounded))
+                .map(|(key, value)| (key.clone(), value.clone())),
+        )
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn apply(&mut self, batch: Batch) -> io::Result<()> {
+        self.apply_in_memory(batch);
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//! Keyspaces, kept apart in the engine by prefixing their keys.
+//!
+//! A key of keyspace `ns` is stored as `"\0ns\0key"`, keys of the default
+//! keyspace are stored as they are and may not start with `\0`, so the
+//! keyspaces never overlap and each one is a single range of the engine.
+//! Keyspace names can't be empty, engine keys starting with `\0\0` hold the
+//! [expiry](crate::lease) of the others.
+
+use std::ops::Bound;
+
+use magnetite::{Refusal, Value};
+
+/// Limits of one keyspace, see [`Storage::quota`](crate::Storage::quota).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+    max_keys: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+impl Quota {
+    pub fn max_keys(mut self, keys: usize) -> Self {
+        self.max_keys = Some(keys);
+        self
+    }
+
+    /// Limit on the length of the keyspace's keys and values, summed.
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    pub(crate) fn allows(&self, usage: Usage) -> bool {
+        self.max_keys.is_none_or(|max| usage.keys <= max)
+            && self.max_bytes.is_none_or(|max| usage.bytes <= max)
+    }
+}
+
+/// What a keyspace holds, as a [`Quota`] counts it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Usage {
+    pub keys: usize,
+    pub bytes: usize,
+}
+
+impl Usage {
+    /// Usage of the key `key` of a keyspace holding `value`.
+    pub fn entry(key: &str, value: &Value) -> Self {
+        Usage {
+            keys: 1,
+            bytes: key.len() + value.as_bytes().len(),
+        }
+    }
+
+    pub fn plus(self, other: Usage) -> Self {
+        Usage {
+            keys: self.keys.saturating_add(other.keys),
+            bytes: self.bytes.saturating_add(other.bytes),
+        }
+    }
+
+    pub fn minus(self, other: Usage) -> Self {
+        Usage {
+            keys: self.keys.saturating_sub(other.keys),
+            bytes: self.bytes.saturating_sub(other.bytes),
+        }
+    }
+}
+
+/// Where the keys of one keyspace live in the engine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Scope {
+    keyspace: Option<String>,
+    prefix: String,
+}
+
+impl Scope {
+    pub fn new(keyspace: Option<&str>) -> Result<Self, Refusal> {
+        let prefix = match keyspace {
+            None => String::new(),
+            Some(keyspace) if keyspace.is_empty() || keyspace.contains('\0') => {
+                return Err(Refusal::Invalid(format!(
+                    "`{}` is not a keyspace name",
+                    keyspace.escape_default()
+                )))
+            }
+            Some(keyspace) => format!("\0{}\0", keyspace),
+        };
+        Ok(Scope {
+            keyspace: keyspace.map(str::to_owned),
+            prefix,
+        })
+    }
+
+    pub fn default_keyspace() -> Self {
+        Scope {
+            keyspace: None,
+            prefix: String::new(),
+        }
+    }
+
+    /// Keyspace of the engine key `scoped`, and its key in there.
+    pub fn split(scoped: &str) -> (Self, &str) {
+        let split = scoped
+            .strip_prefix('\0')
+            .and_then(|rest| rest.split_once('\0'));
+        match split {
+            Some((keyspace, key)) => (
+                Scope {
+                    keyspace: Some(keyspace.to_owned()),
+                    prefix: format!("\0{}\0", keyspace),
+                },
+                key,
+            ),
+            None => (Scope::default_keyspace(), scoped),
+        }
+    }
+
+    pub fn keyspace(&self) -> Option<&str> {
+        self.keyspace.as_deref()
+    }
+
+    /// Engine key of `key`.
+    pub fn key(&self, key: &str) -> Result<String, Refusal> {
+        if self.keyspace.is_none() && key.starts_with('\0') {
+            return Err(Refusal::Invalid(
+                "keys of the default keyspace can't start with \\0".to_owned(),
+            ));
+        }
+        Ok(format!("{}{}", self.prefix, key))
+    }
+
+    /// Key of the keyspace stored under the engine key `scoped`, if it
+    /// belongs to the keyspace.
+    pub fn strip<'a>(&self, scoped: &'a str) -> Option<&'a str> {
+        match &self.keyspace {
+            None if scoped.starts_with('\0') => None,
+            _ => scoped.strip_prefix(self.prefix.as_str()),
+        }
+    }
+
+    /// Start of the engine range holding the keyspace's keys from the
+    /// engine key `start` on, but for the default keyspace's empty key.
+    pub fn start<'a>(&self, start: Bound<&'a str>) -> Bound<&'a str> {
+        match start {
+            // the other keyspaces sort before every key of the default one
+            Bound::Included(key) | Bound::Excluded(key)
+                if self.keyspace.is_none() && key < "\u{1}" =>
+            {
+                Bound::Included("\u{1}")
+            }
+            _ => start,
+        }
+    }
+}
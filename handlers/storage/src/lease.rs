@@ -0,0 +1,370 @@
+//! Keys that expire.
+//!
+//! A `Set` can give its key a time to live, or attach it to a lease granted
+//! to a client, which keeps it alive with `KeepAlive` requests. Expired keys
+//! are deleted on the next [tick](magnetite::ServiceBuilder::tick), like any
+//! other delete, so watchers and the other servers hear of it.
+//!
+//! Reads leave out expired keys that were not ticked away yet. Every server
+//! keeps the deadlines in order, only the ones that came are looked at on
+//! a tick.
+//!
+//! Expiry is stored with the values, under engine keys starting with `\0\0`
+//! that no keyspace can use: the wall-clock deadline or lease of a key is
+//! written in the same batch as the key, and a lease when it is granted, so
+//! they are logged, replicated and synced like any write. Renewals are only
+//! kept by the server answering the requests, after a restart or a failover
+//! every lease lives its whole time to live again. A holder whose
+//! `KeepAlive` is refused with [`Refusal::LeaseExpired`] should grant a new
+//! lease and set its keys again.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ops::Bound;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use magnetite::codec::{Codec, MessagePack};
+use magnetite::{Kind, LeaseId, Refusal, Value, Versioned};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Batch, Op, StorageEngine};
+
+/// Start of the engine keys holding expiry rather than values.
+const RESERVED: &str = "\0\0";
+const EXPIRY: &str = "\0\0expiry\0";
+const LEASE: &str = "\0\0lease\0";
+
+/// Stored under the expiry key of a value.
+#[derive(Deserialize, Serialize)]
+enum Expiry {
+    /// Milliseconds since the UNIX epoch.
+    At(u64),
+    Lease(LeaseId),
+}
+
+/// Stored under the key of a lease.
+#[derive(Deserialize, Serialize)]
+struct Grant {
+    lease: LeaseId,
+    ttl: Duration,
+}
+
+/// When the leases end, as renewed through this server.
+#[derive(Default)]
+pub(crate) struct Leases {
+    deadlines: HashMap<LeaseId, Instant>,
+    /// Read from the engine on the first tick that needs it.
+    index: Option<Index>,
+}
+
+/// Deadlines in the order they come. Entries are not removed when a key or
+/// lease changes, what the engine holds once they come decides.
+#[derive(Default)]
+struct Index {
+    /// Keys with a time to live, by milliseconds since the UNIX epoch.
+    keys: BTreeMap<u64, Vec<String>>,
+    leases: BTreeMap<Instant, Vec<LeaseId>>,
+    held: HashMap<LeaseId, HashSet<String>>,
+}
+
+impl Index {
+    fn read(
+        engine: &dyn StorageEngine,
+        now: Instant,
+        deadlines: &mut HashMap<LeaseId, Instant>,
+    ) -> Self {
+        let mut index = Index::default();
+        for (key, expiry) in records(engine, EXPIRY) {
+            index.expiry(key, expiry);
+        }
+        for (_, grant) in records::<Grant>(engine, LEASE) {
+            if let Some(deadline) = deadline(deadlines, &grant, now) {
+                index.lease(deadline, grant.lease);
+            }
+        }
+        index
+    }
+
+    fn expiry(&mut self, key: String, expiry: Expiry) {
+        match expiry {
+            Expiry::At(at) => self.keys.entry(at).or_default().push(key),
+            Expiry::Lease(id) => {
+                self.held.entry(id).or_default().insert(key);
+            }
+        }
+    }
+
+    fn lease(&mut self, deadline: Instant, id: LeaseId) {
+        self.leases.entry(deadline).or_default().push(id);
+    }
+}
+
+impl Leases {
+    /// Starts `lease`, or renews it when the `Grant` was sent again, returns
+    /// the record to store unless it already is.
+    pub fn grant(
+        &mut self,
+        engine: &dyn StorageEngine,
+        lease: LeaseId,
+        ttl: Duration,
+    ) -> Result<Option<(String, Value)>, Refusal> {
+        let deadline = Instant::now().checked_add(ttl).ok_or_else(too_long)?;
+        self.renewed(lease.clone(), deadline);
+        let key = lease_key(&lease);
+        if engine.get(&key).is_some() {
+            return Ok(None);
+        }
+        Ok(Some((key, record(&Grant { lease, ttl })?)))
+    }
+
+    pub fn keep_alive(&mut self, engine: &dyn StorageEngine, id: &LeaseId) -> Result<(), Refusal> {
+        let ttl = self.live(engine, id)?;
+        let deadline = Instant::now().checked_add(ttl).ok_or_else(too_long)?;
+        self.renewed(id.clone(), deadline);
+        Ok(())
+    }
+
+    /// Ends `lease`, returns the engine keys to delete, its own and the ones
+    /// it held.
+    pub fn revoke(
+        &mut self,
+        engine: &dyn StorageEngine,
+        id: &LeaseId,
+    ) -> Result<Vec<String>, Refusal> {
+        self.live(engine, id)?;
+        Ok(self.end(engine, id))
+    }
+
+    /// Refuses keys to be set with a lease that ended.
+    pub fn check(
+        &mut self,
+        engine: &dyn StorageEngine,
+        lease: Option<&LeaseId>,
+    ) -> Result<(), Refusal> {
+        match lease {
+            Some(id) => self.live(engine, id).map(drop),
+            None => Ok(()),
+        }
+    }
+
+    /// Expiry to store with a key written with `ttl` or `lease`, `None` for
+    /// a permanent key.
+    pub fn expiry(ttl: Option<Duration>, lease: Option<LeaseId>) -> Result<Option<Value>, Refusal> {
+        let expiry = match (lease, ttl) {
+            (Some(id), _) => Expiry::Lease(id),
+            (None, Some(ttl)) => {
+                let at = u64::try_from(ttl.as_millis())
+                    .ok()
+                    .and_then(|ttl| now_millis().checked_add(ttl))
+                    .ok_or_else(too_long)?;
+                Expiry::At(at)
+            }
+            (None, None) => return Ok(None),
+        };
+        record(&expiry).map(Some)
+    }
+
+    /// Engine key of the expiry of the engine key `key`.
+    pub fn expiry_key(key: &str) -> String {
+        format!("{}{}", EXPIRY, key)
+    }
+
+    /// Ends what expired by `now`, returns the engine keys to delete.
+    pub fn expired(&mut self, engine: &dyn StorageEngine, now: Instant) -> Vec<String> {
+        let millis = now_millis();
+        let index = self.index(engine, now);
+        let mut due = Vec::new();
+        while let Some(entry) = index.leases.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+            due.extend(entry.remove());
+        }
+        let mut keys = Vec::new();
+        while let Some(entry) = index.keys.first_entry() {
+            if *entry.key() > millis {
+                break;
+            }
+            keys.extend(entry.remove());
+        }
+        // renewed leases and rewritten keys were indexed again
+        let ended: Vec<LeaseId> = due
+            .into_iter()
+            .filter(|id| engine.get(&lease_key(id)).is_some() && self.live(engine, id).is_err())
+            .collect();
+        let mut keys: Vec<String> = keys
+            .into_iter()
+            .filter(|key| matches!(expiry(engine, key), Some(Expiry::At(at)) if at <= millis))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        for id in &ended {
+            keys.extend(self.end(engine, id));
+        }
+        keys
+    }
+
+    /// Whether the engine key `key` expired by `now`, though it may not
+    /// have been ticked away yet.
+    pub fn is_expired(&self, engine: &dyn StorageEngine, key: &str, now: Instant) -> bool {
+        match expiry(engine, key) {
+            Some(Expiry::At(at)) => at <= now_millis(),
+            Some(Expiry::Lease(id)) => {
+                engine.get(&lease_key(&id)).is_none()
+                    || self.deadlines.get(&id).is_some_and(|end| *end <= now)
+            }
+            None => false,
+        }
+    }
+
+    /// Indexes the expiry a local `batch` stores.
+    pub fn committed(&mut self, batch: &Batch) {
+        let now = Instant::now();
+        let index = match &mut self.index {
+            Some(index) => index,
+            None => return,
+        };
+        for op in &batch.ops {
+            if let Op::Put { key, value } = op {
+                if let Some(key) = key.strip_prefix(EXPIRY) {
+                    if let Ok(expiry) = MessagePack.decode(value.value.as_bytes()) {
+                        index.expiry(key.to_owned(), expiry);
+                    }
+                } else if key.starts_with(LEASE) {
+                    if let Ok(grant) = MessagePack.decode::<Grant>(value.value.as_bytes()) {
+                        if let Some(deadline) = deadline(&mut self.deadlines, &grant, now) {
+                            index.lease(deadline, grant.lease);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the deadlines from the engine again on the next tick, once
+    /// another server changed it.
+    pub fn reindex(&mut self) {
+        self.index = None;
+    }
+
+    /// Forgets the renewals, once another server answers the requests.
+    pub fn forget(&mut self) {
+        self.deadlines.clear();
+        self.index = None;
+    }
+
+    fn end(&mut self, engine: &dyn StorageEngine, id: &LeaseId) -> Vec<String> {
+        self.deadlines.remove(id);
+        let held = self
+            .index(engine, Instant::now())
+            .held
+            .remove(id)
+            .unwrap_or_default();
+        let held = held.into_iter().filter(
+            |key| matches!(expiry(engine, key), Some(Expiry::Lease(lease)) if lease == *id),
+        );
+        std::iter::once(lease_key(id)).chain(held).collect()
+    }
+
+    fn index(&mut self, engine: &dyn StorageEngine, now: Instant) -> &mut Index {
+        let deadlines = &mut self.deadlines;
+        self.index
+            .get_or_insert_with(|| Index::read(engine, now, deadlines))
+    }
+
+    fn renewed(&mut self, id: LeaseId, deadline: Instant) {
+        if let Some(index) = &mut self.index {
+            index.lease(deadline, id.clone());
+        }
+        self.deadlines.insert(id, deadline);
+    }
+
+    /// Time to live of the lease `id`, if it did not end.
+    fn live(&mut self, engine: &dyn StorageEngine, id: &LeaseId) -> Result<Duration, Refusal> {
+        let now = Instant::now();
+        let grant: Option<Grant> = engine
+            .get(&lease_key(id))
+            .and_then(|got| MessagePack.decode(got.value.as_bytes()).ok());
+        // an expired lease may not have been ticked away yet
+        match grant {
+            Some(grant)
+                if deadline(&mut self.deadlines, &grant, now).is_none_or(|end| end > now) =>
+            {
+                Ok(grant.ttl)
+            }
+            _ => Err(Refusal::LeaseExpired { lease: id.clone() }),
+        }
+    }
+}
+
+/// When the lease of `grant` ends, a lease renewed elsewhere lives its
+/// whole time to live from `now`.
+fn deadline(
+    deadlines: &mut HashMap<LeaseId, Instant>,
+    grant: &Grant,
+    now: Instant,
+) -> Option<Instant> {
+    match deadlines.get(&grant.lease) {
+        Some(deadline) => Some(*deadline),
+        None => {
+            let deadline = now.checked_add(grant.ttl)?;
+            deadlines.insert(grant.lease.clone(), deadline);
+            Some(deadline)
+        }
+    }
+}
+
+/// Expiry stored for the engine key `key`.
+fn expiry(engine: &dyn StorageEngine, key: &str) -> Option<Expiry> {
+    let got = engine.get(&Leases::expiry_key(key))?;
+    MessagePack.decode(got.value.as_bytes()).ok()
+}
+
+/// Number of engine keys holding expiry.
+pub(crate) fn reserved(engine: &dyn StorageEngine) -> usize {
+    engine
+        .range(Bound::Included(RESERVED))
+        .take_while(|(key, _)| key.starts_with(RESERVED))
+        .count()
+}
+
+/// Whether the engine key `key` holds expiry rather than a value.
+pub(crate) fn is_reserved(key: &str) -> bool {
+    key.starts_with(RESERVED)
+}
+
+/// Records stored under `prefix`, by the key they are about.
+fn records<'a, T: DeserializeOwned>(
+    engine: &'a dyn StorageEngine,
+    prefix: &'a str,
+) -> impl Iterator<Item = (String, T)> + 'a {
+    engine
+        .range(Bound::Included(prefix))
+        .map_while(move |(key, got)| Some((key.strip_prefix(prefix)?.to_owned(), got)))
+        .filter_map(|(key, got): (String, Versioned)| {
+            Some((key, MessagePack.decode(got.value.as_bytes()).ok()?))
+        })
+}
+
+fn record(record: &impl Serialize) -> Result<Value, Refusal> {
+    let data = MessagePack
+        .encode(record)
+        .map_err(|err| Refusal::Storage(err.to_string()))?;
+    Ok(Value::new(Kind::Bytes, data))
+}
+
+fn lease_key(id: &LeaseId) -> String {
+    format!("{}{}", LEASE, id.0)
+}
+
+fn too_long() -> Refusal {
+    Refusal::Invalid("time to live is too long".to_owned())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
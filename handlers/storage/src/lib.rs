@@ -0,0 +1,989 @@
+//! Key/value storage handler for `magnetite` services.
+//!
+//! [`Storage`] answers the key/value requests of [`magnetite::kv`] from the
+//! mesh, and can be seeded or inspected by the process embedding it while the
+//! service runs:
+//!
+//! ```no_run
+//! # async fn run<B: magnetite::Backend>(backend: B) -> Result<(), Box<dyn std::error::Error>> {
+//! use magnetite::Service;
+//! use magnetite_storage::Storage;
+//!
+//! let storage = Storage::open("/var/lib/configservice")?;
+//! if storage.is_empty() {
+//!     storage.seed_from_file("config.seed")?;
+//! }
+//! storage.insert("configservice.port", "61250")?;
+//!
+//! Service::builder().handler(storage.clone()).build(backend).run().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod crdt;
+pub mod engine;
+pub mod keyspace;
+pub mod lease;
+pub mod log;
+pub mod replication;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use magnetite::handler::lock;
+use magnetite::kv::{ABSENT, DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT};
+use magnetite::{
+    ChangeEvent, Codec, Context, DeleteRequest, Dump, Handler, LeaseId, ListPage, ListRequest,
+    Message, MsgType, PeerId, Refusal, RequestId, Result, SetRequest, Topics, Value, Versioned,
+    WatchRequest,
+};
+use tracing::warn;
+
+use crdt::{Gossip, LwwMap};
+use engine::{Batch, Op};
+use keyspace::{Scope, Usage};
+use lease::Leases;
+use replication::{Replicate, Replicator};
+
+pub use crdt::Crdt;
+pub use engine::{MemoryEngine, StorageEngine};
+pub use keyspace::Quota;
+pub use log::LogEngine;
+pub use replication::Replication;
+
+/// Answers key/value requests from a [`StorageEngine`], in memory unless
+/// told otherwise.
+///
+/// Clones share the same engine, so a clone can be kept around to read or
+/// update the data after the original was handed to a service. Its methods
+/// work on the default keyspace, clients name theirs in their requests, see
+/// [`keyspace`].
+#[derive(Clone)]
+pub struct Storage {
+    db: Arc<Mutex<Db>>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage::with_engine(MemoryEngine::new())
+    }
+}
+
+impl fmt::Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let db = self.db();
+        f.debug_struct("Storage")
+            .field("revision", &db.engine.revision())
+            .field("len", &db.engine.len())
+            .finish()
+    }
+}
+
+impl Storage {
+    pub fn new(db: HashMap<String, String>) -> Self {
+        let storage = Storage::default();
+        // the in-memory engine never fails
+        let _ = storage.seed(db);
+        storage
+    }
+
+    pub fn with_engine(engine: impl StorageEngine) -> Self {
+        Storage {
+            db: Arc::new(Mutex::new(Db {
+                engine: Box::new(engine),
+                watches: HashMap::new(),
+                quotas: HashMap::new(),
+                usage: HashMap::new(),
+                leases: Leases::default(),
+                mode: Mode::Local,
+            })),
+        }
+    }
+
+    /// Storage that survives restarts, kept in the data directory `dir`,
+    /// see [`LogEngine`].
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Storage::with_engine(LogEngine::open(dir)?))
+    }
+
+    /// Keeps the storage in sync with the storages of the other servers of
+    /// the service, see [`replication`].
+    ///
+    /// Only the current leader answers clients. Writes made through
+    /// [`Storage::insert`] or [`Storage::seed`] on a follower are undone
+    /// once it hears from the leader, which it then copies, so make them on
+    /// the leader.
+    pub fn replicate(self, config: Replication) -> Self {
+        self.db().mode = Mode::Leader(Replicator::new(config));
+        self
+    }
+
+    /// Makes the storage eventually consistent with the storages of the
+    /// other servers of the service, see [`crdt`].
+    ///
+    /// Every server answers clients, so a client keeps the first of several
+    /// replies. Versions are handed out by each server on its own: a
+    /// compare-and-swap only holds against the server that answered, and
+    /// watches are refused.
+    pub fn crdt(self, config: Crdt) -> Self {
+        self.db().mode = Mode::Crdt(LwwMap::new(config));
+        self
+    }
+
+    /// Server currently leading a replicated storage, if one was elected.
+    pub fn leader(&self) -> Option<PeerId> {
+        match &self.db().mode {
+            Mode::Leader(replicator) => replicator.leader().cloned(),
+            _ => None,
+        }
+    }
+
+    /// Whether this storage caught up with the other servers since it
+    /// started, always true for a storage kept to itself.
+    pub fn is_ready(&self) -> bool {
+        match &self.db().mode {
+            Mode::Local => true,
+            Mode::Leader(replicator) => replicator.is_ready(),
+            Mode::Crdt(map) => map.is_ready(),
+        }
+    }
+
+    /// Whether this storage answers clients: it leads, is a
+    /// [ready](Storage::is_ready) CRDT, or is kept to itself.
+    pub fn is_leader(&self) -> bool {
+        self.db().answers()
+    }
+
+    /// Creates a storage seeded from a file, see [`Storage::seed_from_file`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let storage = Storage::default();
+        storage.seed_from_file(path)?;
+        Ok(storage)
+    }
+
+    /// Adds every `key = value` line of the file to the storage.
+    ///
+    /// Blank lines and lines starting with `#` are skipped, whitespace around
+    /// keys and values is trimmed.
+    pub fn seed_from_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        let mut entries = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => entries.push((key.trim(), value.trim())),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: expected `key = value`", number + 1),
+                    ))
+                }
+            }
+        }
+        self.seed(entries)
+    }
+
+    /// Inserts all entries at once, overwriting existing keys.
+    pub fn seed<I, K, V>(&self, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        let mut db = self.db();
+        let mut revision = db.engine.revision();
+        let ops = entries
+            .into_iter()
+            .map(|(key, value)| {
+                revision += 1;
+                Op::Put {
+                    key: key.into(),
+                    value: Versioned {
+                        value: value.into(),
+                        version: revision,
+                    },
+                }
+            })
+            .collect();
+        db.seed(Batch { revision, ops })
+    }
+
+    pub fn insert(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> io::Result<Option<Value>> {
+        let old = self.db().write(key.into(), value.into(), None)?;
+        Ok(old.map(|old| old.value))
+    }
+
+    /// The value of `key` as text, byte values are left out like missing
+    /// ones, see [`Storage::get_value`].
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.get_value(key).and_then(|value| value.to_text())
+    }
+
+    pub fn get_value(&self, key: &str) -> Option<Value> {
+        self.get_versioned(key).map(|got| got.value)
+    }
+
+    pub fn get_versioned(&self, key: &str) -> Option<Versioned> {
+        self.db().live(key)
+    }
+
+    pub fn remove(&self, key: &str) -> io::Result<Option<Value>> {
+        let old = self.db().remove(key)?;
+        Ok(old.map(|old| old.value))
+    }
+
+    /// Number of stored keys, of every keyspace.
+    pub fn len(&self) -> usize {
+        let db = self.db();
+        db.engine.len() - lease::reserved(&*db.engine)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Limits what clients can store in `keyspace`, writes that would go
+    /// over it are refused with [`Refusal::OverQuota`].
+    ///
+    /// Only the requests of clients are checked, what the storage is given
+    /// through [`Storage::insert`] or [`Storage::seed`] is stored anyway.
+    pub fn quota(self, keyspace: impl Into<String>, quota: Quota) -> Self {
+        self.db().quotas.insert(keyspace.into(), quota);
+        self
+    }
+
+    /// Removes every key of `keyspace` at once, returns how many there were.
+    pub fn wipe(&self, keyspace: &str) -> io::Result<usize> {
+        let scope = Scope::new(Some(keyspace))
+            .map_err(|refusal| io::Error::new(io::ErrorKind::InvalidInput, refusal.to_string()))?;
+        match self.db().wipe(&scope) {
+            Ok(changes) => Ok(changes.len()),
+            Err(refusal) => Err(io::Error::other(refusal.to_string())),
+        }
+    }
+
+    /// Copy of the default keyspace.
+    pub fn snapshot(&self) -> HashMap<String, Value> {
+        let db = self.db();
+        let scope = Scope::default_keyspace();
+        db.entries(&scope, Bound::Included(""))
+            .map(|(key, got)| (key, got.value))
+            .collect()
+    }
+
+    fn db(&self) -> MutexGuard<'_, Db> {
+        lock(&self.db)
+    }
+}
+
+/// The stored values, and who to tell about changes.
+struct Db {
+    engine: Box<dyn StorageEngine>,
+    watches: HashMap<RequestId, Watcher>,
+    quotas: HashMap<String, Quota>,
+    /// Usage of the keyspaces with a quota, counted on their first write
+    /// and kept up to date by `commit`.
+    usage: HashMap<String, Usage>,
+    leases: Leases,
+    mode: Mode,
+}
+
+/// How a storage keeps up with the other servers.
+enum Mode {
+    Local,
+    Leader(Replicator),
+    Crdt(LwwMap),
+}
+
+struct Watcher {
+    keyspace: Option<String>,
+    request: WatchRequest,
+    /// Reply topic of the `Watch` request, changes are published there.
+    topic: String,
+}
+
+impl Db {
+    fn version(&self, key: &str) -> u64 {
+        self.engine.get(key).map_or(ABSENT, |got| got.version)
+    }
+
+    /// Whether to answer clients.
+    fn answers(&self) -> bool {
+        match &self.mode {
+            Mode::Local => true,
+            Mode::Leader(replicator) => replicator.is_leader(),
+            Mode::Crdt(map) => map.is_ready(),
+        }
+    }
+
+    /// Applies `batch`, and tells the other servers about it.
+    fn commit(&mut self, batch: Batch) -> io::Result<()> {
+        let prev = self.engine.revision();
+        let counted = self.count(&batch);
+        // the deadlines of a batch that fails are dropped once they come
+        self.leases.committed(&batch);
+        match &mut self.mode {
+            Mode::Local => self.engine.apply(batch)?,
+            Mode::Leader(replicator) => {
+                self.engine.apply(batch.clone())?;
+                replicator.committed(prev, &batch);
+            }
+            Mode::Crdt(map) => {
+                self.engine.apply(batch.clone())?;
+                map.committed(&batch);
+            }
+        }
+        for (keyspace, old, new) in counted {
+            if let Some(usage) = self.usage.get_mut(&keyspace) {
+                *usage = usage.minus(old).plus(new);
+            }
+        }
+        Ok(())
+    }
+
+    /// How `batch` changes the usage of the counted keyspaces, what every
+    /// key held before and after it.
+    fn count(&self, batch: &Batch) -> Vec<(String, Usage, Usage)> {
+        let mut counted = Vec::new();
+        for op in &batch.ops {
+            let (scoped, new) = match op {
+                Op::Put { key, value } => (key, Some(&value.value)),
+                Op::Remove { key } => (key, None),
+            };
+            let (scope, key) = Scope::split(scoped);
+            let keyspace = match scope.keyspace() {
+                Some(keyspace) if self.usage.contains_key(keyspace) => keyspace.to_owned(),
+                _ => continue,
+            };
+            let old = self.engine.get(scoped);
+            let usage = |value: Option<&Value>| {
+                value.map_or_else(Usage::default, |value| Usage::entry(key, value))
+            };
+            counted.push((
+                keyspace,
+                usage(old.as_ref().map(|old| &old.value)),
+                usage(new),
+            ));
+        }
+        counted
+    }
+
+    /// Applies seeded values, in CRDT mode any write made elsewhere wins
+    /// over them.
+    fn seed(&mut self, batch: Batch) -> io::Result<()> {
+        match &mut self.mode {
+            Mode::Crdt(map) => {
+                map.seeded(&batch);
+                self.usage.clear();
+                self.leases.reindex();
+                self.engine.apply(batch)
+            }
+            Mode::Local | Mode::Leader(_) => self.commit(batch),
+        }
+    }
+
+    /// Publishes what the mode queued for the other servers.
+    fn flush(&mut self, ctx: &mut Context) -> Result<()> {
+        match &mut self.mode {
+            Mode::Local => Ok(()),
+            Mode::Leader(replicator) => replicator.flush(ctx),
+            Mode::Crdt(map) => map.flush(ctx),
+        }
+    }
+
+    /// Stores `value` at the next version, expiring as `expiry` says,
+    /// returns what it replaced.
+    fn write(
+        &mut self,
+        key: String,
+        value: Value,
+        expiry: Option<Value>,
+    ) -> io::Result<Option<Versioned>> {
+        let old = self.engine.get(&key);
+        let version = self.engine.revision() + 1;
+        let expiry_key = Leases::expiry_key(&key);
+        let mut ops = vec![Op::Put {
+            key,
+            value: Versioned { value, version },
+        }];
+        match expiry {
+            Some(value) => ops.push(Op::Put {
+                key: expiry_key,
+                value: Versioned { value, version },
+            }),
+            None if self.engine.get(&expiry_key).is_some() => {
+                ops.push(Op::Remove { key: expiry_key })
+            }
+            None => {}
+        }
+        self.commit(Batch {
+            revision: version,
+            ops,
+        })?;
+        Ok(old)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<Versioned>> {
+        let old = self.engine.get(key);
+        if old.is_some() {
+            self.commit(Batch {
+                revision: self.engine.revision() + 1,
+                ops: self.removal(key.to_owned()),
+            })?;
+        }
+        Ok(old)
+    }
+
+    /// Ops deleting the engine key `key`, and its expiry.
+    fn removal(&self, key: String) -> Vec<Op> {
+        let expiry_key = Leases::expiry_key(&key);
+        let mut ops = vec![Op::Remove { key }];
+        if self.engine.get(&expiry_key).is_some() {
+            ops.push(Op::Remove { key: expiry_key });
+        }
+        ops
+    }
+
+    fn check(&self, key: &str, scoped: &str, expected: Option<u64>) -> Result<(), Refusal> {
+        match expected {
+            Some(expected) if expected != self.version(scoped) => Err(Refusal::Conflict {
+                key: key.to_owned(),
+                expected,
+                actual: self.version(scoped),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Entries of the keyspace from the engine key `start` on, by key of
+    /// the keyspace.
+    fn entries<'a>(
+        &'a self,
+        scope: &'a Scope,
+        start: Bound<&'a str>,
+    ) -> impl Iterator<Item = (String, Versioned)> + 'a {
+        // the default keyspace's empty key sorts before the other keyspaces
+        let empty = match start {
+            Bound::Included("") if scope.keyspace().is_none() => {
+                self.engine.get("").map(|got| (String::new(), got))
+            }
+            _ => None,
+        };
+        empty.into_iter().chain(
+            self.engine
+                .range(scope.start(start))
+                .map_while(move |(key, got)| Some((scope.strip(&key)?.to_owned(), got))),
+        )
+    }
+
+    /// Refuses to store `value` under `key` if the keyspace would go over
+    /// its quota.
+    fn check_quota(&mut self, scope: &Scope, key: &str, value: &Value) -> Result<(), Refusal> {
+        let keyspace = match scope.keyspace() {
+            Some(keyspace) => keyspace,
+            None => return Ok(()),
+        };
+        let quota = match self.quotas.get(keyspace) {
+            Some(quota) => *quota,
+            None => return Ok(()),
+        };
+        let usage = match self.usage.get(keyspace) {
+            Some(usage) => *usage,
+            None => {
+                let first = scope.key("")?;
+                let usage = self
+                    .entries(scope, Bound::Included(&first))
+                    .fold(Usage::default(), |usage, (key, got)| {
+                        usage.plus(Usage::entry(&key, &got.value))
+                    });
+                self.usage.insert(keyspace.to_owned(), usage);
+                usage
+            }
+        };
+        let old = self.engine.get(&scope.key(key)?);
+        let usage = usage
+            .minus(old.map_or_else(Usage::default, |old| Usage::entry(key, &old.value)))
+            .plus(Usage::entry(key, value));
+        if quota.allows(usage) {
+            Ok(())
+        } else {
+            Err(Refusal::OverQuota {
+                keyspace: keyspace.to_owned(),
+            })
+        }
+    }
+
+    /// The value of the engine key `key`, unless it expired.
+    fn live(&self, key: &str) -> Option<Versioned> {
+        // expired keys are only deleted on the next tick
+        let expired = self.leases.is_expired(&*self.engine, key, Instant::now());
+        self.engine.get(key).filter(|_| !expired)
+    }
+
+    fn expired(&self, scope: &Scope, key: &str, now: Instant) -> bool {
+        scope
+            .key(key)
+            .is_ok_and(|key| self.leases.is_expired(&*self.engine, &key, now))
+    }
+
+    fn get(&self, scope: &Scope, key: String) -> Result<Versioned, Refusal> {
+        match self.live(&scope.key(&key)?) {
+            Some(got) => Ok(got),
+            None => Err(Refusal::NotFound { key }),
+        }
+    }
+
+    fn set(&mut self, scope: &Scope, request: SetRequest) -> Result<(u64, ChangeEvent), Refusal> {
+        let scoped = scope.key(&request.key)?;
+        self.check(&request.key, &scoped, request.expected_version)?;
+        self.check_quota(scope, &request.key, &request.value)?;
+        self.leases.check(&*self.engine, request.lease.as_ref())?;
+        let expiry = Leases::expiry(request.ttl, request.lease)?;
+        let old = self
+            .write(scoped, request.value.clone(), expiry)
+            .map_err(storage_failed)?;
+        let new = Versioned {
+            value: request.value,
+            version: self.engine.revision(),
+        };
+        let change = ChangeEvent {
+            key: request.key,
+            old,
+            new: Some(new),
+        };
+        Ok((self.engine.revision(), change))
+    }
+
+    fn delete(
+        &mut self,
+        scope: &Scope,
+        request: DeleteRequest,
+    ) -> Result<Option<ChangeEvent>, Refusal> {
+        let scoped = scope.key(&request.key)?;
+        self.check(&request.key, &scoped, request.expected_version)?;
+        let old = self.remove(&scoped).map_err(storage_failed)?;
+        Ok(old.map(|old| ChangeEvent {
+            key: request.key,
+            old: Some(old),
+            new: None,
+        }))
+    }
+
+    fn exists(&self, scope: &Scope, key: String) -> Result<bool, Refusal> {
+        Ok(self.live(&scope.key(&key)?).is_some())
+    }
+
+    fn list(&self, scope: &Scope, request: ListRequest) -> Result<ListPage, Refusal> {
+        let limit = request
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT) as usize;
+        let (from, after) = match &request.cursor {
+            Some(cursor) if *cursor >= request.prefix => (scope.key(cursor)?, true),
+            _ => (scope.key(&request.prefix)?, false),
+        };
+        let start = match after {
+            true => Bound::Excluded(from.as_str()),
+            false => Bound::Included(from.as_str()),
+        };
+        let now = Instant::now();
+        let mut keys: Vec<String> = self
+            .entries(scope, start)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&request.prefix))
+            .filter(|key| !self.expired(scope, key, now))
+            .take(limit + 1)
+            .collect();
+        let next = if keys.len() > limit {
+            keys.truncate(limit);
+            keys.last().cloned()
+        } else {
+            None
+        };
+        Ok(ListPage { keys, next })
+    }
+
+    fn dump(&self, scope: &Scope) -> Result<Dump, Refusal> {
+        let first = scope.key("")?;
+        let now = Instant::now();
+        Ok(Dump {
+            revision: self.engine.revision(),
+            entries: self
+                .entries(scope, Bound::Included(&first))
+                .filter(|(key, _)| !self.expired(scope, key, now))
+                .collect(),
+        })
+    }
+
+    /// Removes every key of the keyspace in one batch.
+    fn wipe(&mut self, scope: &Scope) -> Result<Vec<ChangeEvent>, Refusal> {
+        if scope.keyspace().is_none() {
+            return Err(Refusal::Invalid(
+                "the default keyspace can't be wiped".to_owned(),
+            ));
+        }
+        let first = scope.key("")?;
+        let changes: Vec<ChangeEvent> = self
+            .entries(scope, Bound::Included(&first))
+            .map(|(key, old)| ChangeEvent {
+                key,
+                old: Some(old),
+                new: None,
+            })
+            .collect();
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        let mut ops = Vec::new();
+        for change in &changes {
+            ops.extend(self.removal(scope.key(&change.key)?));
+        }
+        self.commit(Batch {
+            revision: self.engine.revision() + 1,
+            ops,
+        })
+        .map_err(storage_failed)?;
+        Ok(changes)
+    }
+
+    fn grant(&mut self, id: RequestId, ttl: Duration) -> Result<LeaseId, Refusal> {
+        let lease = LeaseId(id);
+        if let Some((key, value)) = self.leases.grant(&*self.engine, lease.clone(), ttl)? {
+            let version = self.engine.revision() + 1;
+            self.commit(Batch {
+                revision: version,
+                ops: vec![Op::Put {
+                    key,
+                    value: Versioned { value, version },
+                }],
+            })
+            .map_err(storage_failed)?;
+        }
+        Ok(lease)
+    }
+
+    fn revoke(&mut self, lease: &LeaseId) -> Result<Vec<(Scope, ChangeEvent)>, Refusal> {
+        let keys = self.leases.revoke(&*self.engine, lease)?;
+        self.expire(keys).map_err(storage_failed)
+    }
+
+    /// Removes the engine keys `keys` in one batch, returns the changes
+    /// to values with the keyspace they were made in.
+    fn expire(&mut self, keys: Vec<String>) -> io::Result<Vec<(Scope, ChangeEvent)>> {
+        let mut ops = Vec::new();
+        let mut changes = Vec::new();
+        for scoped in keys {
+            let old = match self.engine.get(&scoped) {
+                Some(old) => old,
+                None => continue,
+            };
+            if !lease::is_reserved(&scoped) {
+                let (scope, key) = Scope::split(&scoped);
+                changes.push((
+                    scope,
+                    ChangeEvent {
+                        key: key.to_owned(),
+                        old: Some(old),
+                        new: None,
+                    },
+                ));
+            }
+            ops.extend(self.removal(scoped));
+        }
+        if !ops.is_empty() {
+            self.commit(Batch {
+                revision: self.engine.revision() + 1,
+                ops,
+            })?;
+        }
+        Ok(changes)
+    }
+
+    fn watch(
+        &mut self,
+        id: RequestId,
+        scope: &Scope,
+        request: WatchRequest,
+        topic: String,
+    ) -> Result<(), Refusal> {
+        if let Mode::Crdt(_) = self.mode {
+            return Err(Refusal::Unsupported(
+                "watches need a leader, this storage is a CRDT".to_owned(),
+            ));
+        }
+        self.watches.insert(
+            id,
+            Watcher {
+                keyspace: scope.keyspace().map(str::to_owned),
+                request,
+                topic,
+            },
+        );
+        Ok(())
+    }
+
+    fn unwatch(&mut self, message: &Message, ctx: &Context) -> Result<()> {
+        let watch: RequestId = ctx.format().decode(&message.payload)?;
+        // only the watcher can end its watch
+        if &watch.peer == ctx.source() {
+            self.watches.remove(&watch);
+        }
+        Ok(())
+    }
+
+    /// Publishes `change` to every watcher of its key.
+    fn notify(&self, scope: &Scope, change: &ChangeEvent, ctx: &mut Context) -> Result<()> {
+        let mut payload = None;
+        for (id, watcher) in &self.watches {
+            if watcher.keyspace.as_deref() != scope.keyspace()
+                || !watcher.request.matches(&change.key)
+            {
+                continue;
+            }
+            let payload = match &payload {
+                Some(payload) => payload,
+                None => payload.insert(ctx.topics().codec().encode(change)?),
+            };
+            ctx.publish(
+                watcher.topic.clone(),
+                Message {
+                    id: id.clone(),
+                    msgtype: MsgType::Notification,
+                    payload: payload.clone(),
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Handler for Storage {
+    fn handle(&mut self, message: &Message, ctx: &mut Context) -> Result<()> {
+        let mut db = self.db();
+        let db = &mut *db;
+        let replicated = ctx.topic() == ctx.topics().replication();
+        match &mut db.mode {
+            Mode::Local => {}
+            Mode::Leader(replicator) => {
+                replicator.attach(ctx);
+                if replicated {
+                    let received: Replicate = ctx.topics().codec().decode(&message.payload)?;
+                    let revision = db.engine.revision();
+                    replicator.receive(ctx.source().clone(), received, &mut *db.engine);
+                    // what the leader wrote is counted and indexed again
+                    if db.engine.revision() != revision {
+                        db.usage.clear();
+                        db.leases.reindex();
+                    }
+                    return replicator.flush(ctx);
+                }
+            }
+            Mode::Crdt(map) => {
+                map.attach(ctx);
+                if replicated {
+                    let gossip: Gossip = ctx.topics().codec().decode(&message.payload)?;
+                    let revision = db.engine.revision();
+                    map.receive(ctx.source().clone(), gossip, &mut *db.engine);
+                    // what the other servers wrote is counted and indexed again
+                    if db.engine.revision() != revision {
+                        db.usage.clear();
+                        db.leases.reindex();
+                    }
+                    return map.flush(ctx);
+                }
+            }
+        }
+        let scope = Scope::new(ctx.keyspace());
+        // followers keep track of watches, to notify them once they lead,
+        // servers still syncing stay silent
+        if !db.answers() {
+            match message.msgtype {
+                MsgType::Watch => {
+                    let topic = ctx.reply_topic(&message.id);
+                    if let (Ok(scope), Ok(request)) = (&scope, ctx.decode(message)) {
+                        let _ = db.watch(message.id.clone(), scope, request, topic);
+                    }
+                }
+                MsgType::Unwatch => db.unwatch(message, ctx)?,
+                _ => {}
+            }
+            return Ok(());
+        }
+        let scope = match scope {
+            Ok(scope) => scope,
+            Err(refusal) => {
+                return match message.msgtype {
+                    MsgType::Control
+                    | MsgType::Notification
+                    | MsgType::Unwatch
+                    | MsgType::Announce
+                    | MsgType::Withdraw
+                    | MsgType::Resolve
+                    | MsgType::Subscribe
+                    | MsgType::Forward
+                    | MsgType::Other(_) => Ok(()),
+                    _ => ctx.reply_result(message, &Err::<(), _>(refusal)),
+                }
+            }
+        };
+        match message.msgtype {
+            MsgType::Get => {
+                let reply = ctx.decode(message).and_then(|key| db.get(&scope, key));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Set => {
+                let reply = ctx
+                    .decode(message)
+                    .and_then(|request| db.set(&scope, request));
+                ctx.reply_result(
+                    message,
+                    &reply
+                        .as_ref()
+                        .map(|(version, _)| *version)
+                        .map_err(Refusal::clone),
+                )?;
+                if let Ok((_, change)) = &reply {
+                    db.notify(&scope, change, ctx)?;
+                }
+            }
+            MsgType::Delete => {
+                let reply = ctx
+                    .decode(message)
+                    .and_then(|request| db.delete(&scope, request));
+                ctx.reply_result(
+                    message,
+                    &reply.as_ref().map(Option::is_some).map_err(Refusal::clone),
+                )?;
+                if let Ok(Some(change)) = &reply {
+                    db.notify(&scope, change, ctx)?;
+                }
+            }
+            MsgType::Exists => {
+                let reply = ctx.decode(message).and_then(|key| db.exists(&scope, key));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::List => {
+                let reply = ctx
+                    .decode(message)
+                    .and_then(|request| db.list(&scope, request));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Watch => {
+                let topic = ctx.reply_topic(&message.id);
+                let reply = ctx
+                    .decode(message)
+                    .and_then(|request| db.watch(message.id.clone(), &scope, request, topic));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Unwatch => db.unwatch(message, ctx)?,
+            MsgType::Dump => {
+                let reply = db.dump(&scope);
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Wipe => {
+                let reply = db.wipe(&scope);
+                ctx.reply_result(
+                    message,
+                    &reply
+                        .as_ref()
+                        .map(|changes| changes.len() as u64)
+                        .map_err(Refusal::clone),
+                )?;
+                for change in reply.iter().flatten() {
+                    db.notify(&scope, change, ctx)?;
+                }
+            }
+            MsgType::Grant => {
+                let reply = ctx
+                    .decode(message)
+                    .and_then(|ttl| db.grant(message.id.clone(), ttl));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::KeepAlive => {
+                let reply = ctx
+                    .decode(message)
+                    .and_then(|lease| db.leases.keep_alive(&*db.engine, &lease));
+                ctx.reply_result(message, &reply)?;
+            }
+            MsgType::Revoke => {
+                let reply = ctx.decode(message).and_then(|lease| db.revoke(&lease));
+                ctx.reply_result(
+                    message,
+                    &reply
+                        .as_ref()
+                        .map(|changes| changes.len() as u64)
+                        .map_err(Refusal::clone),
+                )?;
+                for (scope, change) in reply.iter().flatten() {
+                    db.notify(scope, change, ctx)?;
+                }
+            }
+            // replies, messages for other handlers, and types from newer peers
+            _ => {}
+        }
+        db.flush(ctx)
+    }
+
+    fn subscriptions(&self, topics: &Topics) -> Vec<String> {
+        match self.db().mode {
+            Mode::Local => Vec::new(),
+            Mode::Leader(_) | Mode::Crdt(_) => vec![topics.replication()],
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context) -> Result<()> {
+        let mut db = self.db();
+        let db = &mut *db;
+        match &mut db.mode {
+            Mode::Local => {}
+            Mode::Leader(replicator) => {
+                replicator.attach(ctx);
+                replicator.tick(db.engine.revision());
+            }
+            Mode::Crdt(map) => {
+                map.attach(ctx);
+                map.tick(&*db.engine);
+            }
+        }
+        if db.answers() {
+            let expired = db.leases.expired(&*db.engine, Instant::now());
+            match db.expire(expired) {
+                Ok(changes) => {
+                    for (scope, change) in &changes {
+                        db.notify(scope, change, ctx)?;
+                    }
+                }
+                Err(err) => warn!("failed to delete expired keys: {}", err),
+            }
+        } else {
+            // renewals go to the server answering, its leases start afresh
+            db.leases.forget();
+        }
+        db.flush(ctx)
+    }
+
+    fn disconnected(&mut self, peer: &PeerId) {
+        let mut db = self.db();
+        db.watches.retain(|id, _| id.peer != *peer);
+        if let Mode::Leader(replicator) = &mut db.mode {
+            replicator.disconnected(peer);
+        }
+    }
+}
+
+fn storage_failed(err: io::Error) -> Refusal {
+    Refusal::Storage(err.to_string())
+}
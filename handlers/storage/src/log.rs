@@ -0,0 +1,207 @@
+//! Durable [`StorageEngine`] made of an append-only log and snapshots.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use magnetite::codec::MessagePack;
+use magnetite::{Codec, Versioned};
+use tracing::warn;
+
+use crate::engine::{Batch, MemoryEngine, StorageEngine};
+
+const LOG: &str = "log";
+const SNAPSHOT: &str = "snapshot";
+const SNAPSHOT_TMP: &str = "snapshot.tmp";
+
+/// Keeps a copy of everything in memory, and every applied [`Batch`] in a
+/// log inside the data directory.
+///
+/// Once the log holds [`LogEngine::snapshot_every`] batches the whole state
+/// is written to a snapshot and the log starts over. Opening the directory
+/// again loads the snapshot and replays the log; a batch torn by a crash
+/// while it was appended is dropped.
+///
+/// A batch that fails to be logged is cut from the log again, if even that
+/// fails the engine refuses every later batch until it is opened again.
+pub struct LogEngine {
+    dir: PathBuf,
+    state: MemoryEngine,
+    log: File,
+    batches: usize,
+    snapshot_every: usize,
+    sync: bool,
+    /// Set when a failed batch could not be cut from the log.
+    damaged: bool,
+}
+
+impl LogEngine {
+    /// Opens the data directory, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut state = match fs::read(dir.join(SNAPSHOT)) {
+            Ok(data) => MessagePack.decode(&data).map_err(invalid_data)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => MemoryEngine::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(LOG))?;
+        let mut data = Vec::new();
+        log.read_to_end(&mut data)?;
+        let (batches, valid) = replay(&data, &mut state);
+        if valid < data.len() {
+            warn!(
+                "dropping {} bytes of torn log in {}",
+                data.len() - valid,
+                dir.display()
+            );
+            log.set_len(valid as u64)?;
+        }
+
+        Ok(LogEngine {
+            dir,
+            state,
+            log,
+            batches,
+            snapshot_every: 1000,
+            sync: true,
+            damaged: false,
+        })
+    }
+
+    /// Number of logged batches that triggers a snapshot.
+    pub fn snapshot_every(mut self, batches: usize) -> Self {
+        self.snapshot_every = batches.max(1);
+        self
+    }
+
+    /// Whether every batch is flushed to disk before [`StorageEngine::apply`]
+    /// returns, on by default. Without it a power loss can lose the last
+    /// writes, but not corrupt the data.
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Writes the whole state to a snapshot, and empties the log.
+    pub fn snapshot(&mut self) -> io::Result<()> {
+        let data = MessagePack.encode(&self.state).map_err(invalid_data)?;
+        let tmp = self.dir.join(SNAPSHOT_TMP);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(SNAPSHOT))?;
+        sync_dir(&self.dir)?;
+        // a crash before the log is emptied only replays batches already
+        // in the snapshot, which changes nothing
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.batches = 0;
+        Ok(())
+    }
+
+    /// Appends a record to the log, synced unless asked not to.
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        self.log.write_all(record)?;
+        if self.sync {
+            self.log.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+impl StorageEngine for LogEngine {
+    fn revision(&self) -> u64 {
+        self.state.revision()
+    }
+
+    fn get(&self, key: &str) -> Option<Versioned> {
+        self.state.get(key)
+    }
+
+    fn range(&self, start: Bound<&str>) -> Box<dyn Iterator<Item = (String, Versioned)> + '_> {
+        self.state.range(start)
+    }
+
+    fn len(&self) -> usize {
+        self.state.len()
+    }
+
+    fn apply(&mut self, batch: Batch) -> io::Result<()> {
+        if self.damaged {
+            return Err(io::Error::other(format!(
+                "the log in {} is damaged, open it again",
+                self.dir.display()
+            )));
+        }
+        let data = MessagePack.encode(&batch).map_err(invalid_data)?;
+        let mut record = Vec::with_capacity(4 + data.len());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&data);
+        let logged = self.log.metadata()?.len();
+        if let Err(err) = self.append(&record) {
+            // a torn record would hide the batches logged after it
+            if self.log.set_len(logged).is_err() {
+                self.damaged = true;
+            }
+            return Err(err);
+        }
+        self.state.apply_in_memory(batch);
+
+        self.batches += 1;
+        if self.batches >= self.snapshot_every {
+            // the batch is logged, the next one tries again
+            if let Err(err) = self.snapshot() {
+                warn!("failed to snapshot {}: {}", self.dir.display(), err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Makes a rename inside `dir` durable.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Applies the batches of a log to `state`, returns how many there were and
+/// the length of the log they were read from.
+fn replay(data: &[u8], state: &mut MemoryEngine) -> (usize, usize) {
+    let mut batches = 0;
+    let mut offset = 0;
+    while let Some(header) = data.get(offset..offset + 4) {
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let batch: Batch = match data
+            .get(offset + 4..offset + 4 + len)
+            .map(|record| MessagePack.decode(record))
+        {
+            Some(Ok(batch)) => batch,
+            _ => break,
+        };
+        state.apply_in_memory(batch);
+        batches += 1;
+        offset += 4 + len;
+    }
+    (batches, offset)
+}
+
+fn invalid_data(err: magnetite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
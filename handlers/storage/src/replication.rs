@@ -0,0 +1,433 @@
+//! Leader-based replication of a [`Storage`](crate::Storage) across servers.
+//!
+//! The servers of a replicated storage heartbeat on
+//! [`Topics::replication`](magnetite::Topics::replication) and follow one
+//! leader, in the spirit of Raft:
+//!
+//! * only the leader answers clients, and every batch it applies is
+//!   published to the followers, which apply it in order;
+//! * when the leader goes silent for [`Replication::timeout`], the most up
+//!   to date server left (lowest peer id on ties) claims the next term;
+//! * a follower that missed a batch, or is at another revision than the
+//!   leader for any reason, asks the leader for a snapshot and takes its
+//!   copy as is; a starting server is [ready](crate::Storage::is_ready) once
+//!   it caught up with the revision the leader announces.
+//!
+//! Snapshots are sent in parts of about [`MAX_CHUNK_BYTES`], so they fit in
+//! a single message of the backend whatever the size of the storage.
+//!
+//! Replication is asynchronous, a write acknowledged by a leader that dies
+//! before publishing it is lost.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::time::{Duration, Instant};
+
+use magnetite::request::RequestIds;
+use magnetite::{Codec, Context, Message, MsgType, PeerId, Result, Versioned};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::engine::{Batch, Op, StorageEngine};
+
+/// Size of the entries a single replication message carries at most, well
+/// under the 64 KiB gossipsub accepts by default.
+pub const MAX_CHUNK_BYTES: usize = 32 * 1024;
+
+/// Settings of a replicated storage, see [`Storage::replicate`](crate::Storage::replicate).
+#[derive(Clone, Debug)]
+pub struct Replication {
+    timeout: Duration,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Replication {
+            timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+impl Replication {
+    /// How long a server may stay silent before the others consider it
+    /// gone, should span a few [`ServiceBuilder::tick`](magnetite::ServiceBuilder::tick)s.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// What the servers tell each other.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) enum Replicate {
+    Heartbeat {
+        term: u64,
+        leader: Option<PeerId>,
+        revision: u64,
+    },
+    /// A batch the leader applied on top of revision `prev`.
+    Batch {
+        term: u64,
+        prev: u64,
+        batch: Batch,
+    },
+    SyncRequest {
+        revision: u64,
+    },
+    /// Part `part` of `parts` of the leader's entries at `revision`.
+    Snapshot {
+        to: PeerId,
+        revision: u64,
+        part: u32,
+        parts: u32,
+        entries: Vec<(String, Versioned)>,
+    },
+}
+
+struct Peer {
+    revision: u64,
+    last_seen: Instant,
+}
+
+/// The parts of a snapshot received so far.
+struct Incoming {
+    leader: PeerId,
+    revision: u64,
+    parts: u32,
+    received: u32,
+    entries: Vec<(String, Versioned)>,
+}
+
+pub(crate) struct Replicator {
+    config: Replication,
+    ids: Option<RequestIds>,
+    started: Instant,
+    term: u64,
+    leader: Option<PeerId>,
+    ready: bool,
+    peers: HashMap<PeerId, Peer>,
+    last_sync: Option<Instant>,
+    incoming: Option<Incoming>,
+    outbox: Vec<Replicate>,
+}
+
+impl Replicator {
+    pub fn new(config: Replication) -> Self {
+        Replicator {
+            config,
+            ids: None,
+            started: Instant::now(),
+            term: 0,
+            leader: None,
+            ready: false,
+            peers: HashMap::new(),
+            last_sync: None,
+            incoming: None,
+            outbox: Vec::new(),
+        }
+    }
+
+    fn local(&self) -> Option<&PeerId> {
+        self.ids.as_ref().map(RequestIds::peer)
+    }
+
+    pub fn leader(&self) -> Option<&PeerId> {
+        self.leader.as_ref()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader.is_some() && self.leader.as_ref() == self.local()
+    }
+
+    /// Whether we lead, or caught up with the leader once.
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    fn caught_up(&mut self, revision: u64) {
+        if !self.ready {
+            info!("caught up at revision {}, ready", revision);
+            self.ready = true;
+        }
+    }
+
+    /// Learns the local peer id, the first time a handler call sees it.
+    pub fn attach(&mut self, ctx: &Context) {
+        if self.ids.is_none() {
+            self.ids = Some(RequestIds::new(ctx.local().clone()));
+        }
+    }
+
+    /// Queues `batch` for the followers if this server leads.
+    pub fn committed(&mut self, prev: u64, batch: &Batch) {
+        if self.is_leader() {
+            self.outbox.push(Replicate::Batch {
+                term: self.term,
+                prev,
+                batch: batch.clone(),
+            });
+        }
+    }
+
+    /// Forgets silent peers, elects a new leader if needed, and heartbeats.
+    pub fn tick(&mut self, revision: u64) {
+        let now = Instant::now();
+        let timeout = self.config.timeout;
+        self.peers
+            .retain(|_, peer| now.duration_since(peer.last_seen) < timeout);
+
+        let leader_alive = match &self.leader {
+            Some(leader) => Some(leader) == self.local() || self.peers.contains_key(leader),
+            None => false,
+        };
+        // give the others a chance to be heard before claiming anything
+        if !leader_alive && now.duration_since(self.started) >= timeout {
+            let local = self.local().cloned();
+            let candidate = self
+                .peers
+                .iter()
+                .map(|(id, peer)| (peer.revision, Reverse(id.clone())))
+                .chain(local.clone().map(|local| (revision, Reverse(local))))
+                .max()
+                .map(|(_, Reverse(id))| id);
+            if candidate.is_some() && candidate == local {
+                self.term += 1;
+                info!("leading term {} at revision {}", self.term, revision);
+            }
+            self.leader = candidate.filter(|candidate| Some(candidate) == local.as_ref());
+        }
+        if self.is_leader() {
+            self.caught_up(revision);
+        }
+
+        self.outbox.push(Replicate::Heartbeat {
+            term: self.term,
+            leader: self.leader.clone(),
+            revision,
+        });
+    }
+
+    /// Handles a message from `from`, applying what the leader replicated.
+    pub fn receive(&mut self, from: PeerId, message: Replicate, engine: &mut dyn StorageEngine) {
+        let now = Instant::now();
+        match message {
+            Replicate::Heartbeat {
+                term,
+                leader,
+                revision,
+            } => {
+                self.seen(&from, revision, now);
+                if term > self.term {
+                    self.term = term;
+                    self.leader = leader;
+                } else if term == self.term {
+                    if let Some(claimant) = leader {
+                        if self.outranks(&claimant, engine.revision()) {
+                            self.leader = Some(claimant);
+                        }
+                    }
+                }
+                // also catches up servers that started after the last write
+                let own = engine.revision();
+                if self.leader.as_ref() == Some(&from) {
+                    if revision != own {
+                        self.request_sync(own, now);
+                    } else {
+                        self.caught_up(own);
+                    }
+                }
+            }
+            Replicate::Batch { term, prev, batch } => {
+                if term < self.term {
+                    return;
+                }
+                if term > self.term || self.leader.is_none() {
+                    self.term = term;
+                    self.leader = Some(from.clone());
+                }
+                if self.leader.as_ref() != Some(&from) {
+                    return;
+                }
+                self.seen(&from, batch.revision, now);
+
+                // a follower behind missed a batch, one ahead wrote on its own
+                let revision = engine.revision();
+                if prev == revision {
+                    if let Err(err) = engine.apply(batch) {
+                        warn!("failed to apply replicated batch: {}", err);
+                    }
+                } else {
+                    self.request_sync(revision, now);
+                }
+            }
+            Replicate::SyncRequest { .. } => {
+                if self.is_leader() {
+                    let revision = engine.revision();
+                    let entries = engine.range(Bound::Unbounded).collect();
+                    // with some room for the version and the encoding
+                    let parts = chunks(entries, |(key, got)| {
+                        key.len() + got.value.as_bytes().len() + 32
+                    });
+                    let count = parts.len() as u32;
+                    for (part, entries) in parts.into_iter().enumerate() {
+                        self.outbox.push(Replicate::Snapshot {
+                            to: from.clone(),
+                            revision,
+                            part: part as u32,
+                            parts: count,
+                            entries,
+                        });
+                    }
+                }
+            }
+            Replicate::Snapshot {
+                to,
+                revision,
+                part,
+                parts,
+                entries,
+            } => {
+                if Some(&to) != self.local() || self.leader.as_ref() != Some(&from) {
+                    return;
+                }
+                if part == 0 {
+                    self.incoming = Some(Incoming {
+                        leader: from.clone(),
+                        revision,
+                        parts,
+                        received: 0,
+                        entries: Vec::new(),
+                    });
+                }
+                let mut incoming = match self.incoming.take() {
+                    Some(incoming)
+                        if incoming.leader == from
+                            && incoming.revision == revision
+                            && incoming.received == part =>
+                    {
+                        incoming
+                    }
+                    // a part went missing, the next heartbeat asks again
+                    _ => return,
+                };
+                incoming.entries.extend(entries);
+                incoming.received += 1;
+                if incoming.received < incoming.parts {
+                    self.incoming = Some(incoming);
+                    return;
+                }
+                match engine.apply(catch_up(&*engine, revision, incoming.entries)) {
+                    Ok(()) => self.caught_up(engine.revision()),
+                    Err(err) => warn!("failed to apply snapshot: {}", err),
+                }
+            }
+        }
+    }
+
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    fn seen(&mut self, peer: &PeerId, revision: u64, now: Instant) {
+        let peer = self.peers.entry(peer.clone()).or_insert(Peer {
+            revision,
+            last_seen: now,
+        });
+        peer.revision = peer.revision.max(revision);
+        peer.last_seen = now;
+    }
+
+    /// Whether `claimant` should lead rather than the current leader, for
+    /// two claims on the same term.
+    fn outranks(&self, claimant: &PeerId, revision: u64) -> bool {
+        let rank = |id: &PeerId| {
+            let revision = if Some(id) == self.local() {
+                revision
+            } else {
+                self.peers.get(id).map_or(0, |peer| peer.revision)
+            };
+            (revision, Reverse(id.clone()))
+        };
+        match &self.leader {
+            Some(leader) => leader != claimant && rank(claimant) > rank(leader),
+            None => true,
+        }
+    }
+
+    fn request_sync(&mut self, revision: u64, now: Instant) {
+        let due = self
+            .last_sync
+            .is_none_or(|last| now.duration_since(last) >= self.config.timeout);
+        if due {
+            self.last_sync = Some(now);
+            self.outbox.push(Replicate::SyncRequest { revision });
+        }
+    }
+
+    /// Publishes the queued messages on the replication topic.
+    pub fn flush(&mut self, ctx: &mut Context) -> Result<()> {
+        match &mut self.ids {
+            Some(ids) => publish(ctx, ids, self.outbox.drain(..)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Publishes `messages` on the replication topic, as control messages.
+pub(crate) fn publish<T: Serialize>(
+    ctx: &mut Context,
+    ids: &mut RequestIds,
+    messages: impl IntoIterator<Item = T>,
+) -> Result<()> {
+    let topic = ctx.topics().replication();
+    for message in messages {
+        let payload = ctx.topics().codec().encode(&message)?;
+        ctx.publish(
+            topic.clone(),
+            Message {
+                id: ids.next_id(),
+                msgtype: MsgType::Control,
+                payload,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Splits `items` into chunks of at most [`MAX_CHUNK_BYTES`] as measured by
+/// `size`, an item larger than that gets a chunk of its own. There is always
+/// at least one chunk, so nothing to send still makes a message.
+pub(crate) fn chunks<T>(items: Vec<T>, size: impl Fn(&T) -> usize) -> Vec<Vec<T>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut bytes = 0;
+    for item in items {
+        let item_bytes = size(&item);
+        if !chunk.is_empty() && bytes + item_bytes > MAX_CHUNK_BYTES {
+            chunks.push(std::mem::take(&mut chunk));
+            bytes = 0;
+        }
+        bytes += item_bytes;
+        chunk.push(item);
+    }
+    chunks.push(chunk);
+    chunks
+}
+
+/// Batch turning the engine's state into the leader's snapshot, revision
+/// included, even if the engine was ahead.
+fn catch_up(engine: &dyn StorageEngine, revision: u64, entries: Vec<(String, Versioned)>) -> Batch {
+    let entries: BTreeMap<String, Versioned> = entries.into_iter().collect();
+    let mut ops: Vec<Op> = engine
+        .range(Bound::Unbounded)
+        .filter(|(key, _)| !entries.contains_key(key))
+        .map(|(key, _)| Op::Remove { key })
+        .collect();
+    for (key, value) in entries {
+        if engine.get(&key).as_ref() != Some(&value) {
+            ops.push(Op::Put { key, value });
+        }
+    }
+    Batch { revision, ops }
+}
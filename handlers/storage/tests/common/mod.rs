@@ -0,0 +1,20 @@
+#![allow(dead_code)]
+
+use magnetite::{Client, Service};
+use magnetite_memory::MemoryNetwork;
+use magnetite_storage::Storage;
+
+/// Serves `storage` on a fresh in-memory network.
+pub fn serve(storage: &Storage) -> MemoryNetwork {
+    let network = MemoryNetwork::new();
+    Service::builder()
+        .handler(storage.clone())
+        .build(network.join("server"))
+        .spawn();
+    network
+}
+
+/// Serves `storage` on a fresh in-memory network, returns a client of it.
+pub fn start(storage: &Storage) -> Client {
+    Client::builder().build(serve(storage).join("client"))
+}
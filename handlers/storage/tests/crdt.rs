@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use magnetite::request::RequestIds;
+use magnetite::{
+    Backend, Client, Codec, Envelope, Format, Message, MsgType, PeerId, Refusal, Service, Topics,
+    Value,
+};
+use magnetite_memory::MemoryNetwork;
+use magnetite_storage::crdt::Stamp;
+use magnetite_storage::{Crdt, Storage};
+use serde::Serialize;
+use tokio::time::{sleep, timeout};
+
+fn crdt() -> Storage {
+    Storage::default().crdt(
+        Crdt::default()
+            .gossip_every(Duration::from_millis(50))
+            .sync_timeout(Duration::from_millis(100)),
+    )
+}
+
+fn serve(network: &MemoryNetwork, name: &str, storage: &Storage) {
+    Service::builder()
+        .tick(Duration::from_millis(20))
+        .handler(storage.clone())
+        .build(network.join(name))
+        .spawn();
+}
+
+async fn settle(storage: &Storage, key: &str, value: Option<&str>) {
+    let settled = async {
+        while storage.get(key).as_deref() != value {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), settled)
+        .await
+        .unwrap_or_else(|_| panic!("`{}` never became {:?}", key, value));
+}
+
+#[tokio::test]
+async fn local_writes_are_gossiped() {
+    let network = MemoryNetwork::new();
+    let (a, b) = (crdt(), crdt());
+    serve(&network, "server-a", &a);
+    serve(&network, "server-b", &b);
+
+    a.insert("configservice.port", "61250").unwrap();
+    settle(&b, "configservice.port", Some("61250")).await;
+
+    b.remove("configservice.port").unwrap();
+    settle(&a, "configservice.port", None).await;
+}
+
+#[tokio::test]
+async fn concurrent_writes_converge() {
+    let network = MemoryNetwork::new();
+    let (a, b) = (crdt(), crdt());
+    serve(&network, "server-a", &a);
+    serve(&network, "server-b", &b);
+
+    a.insert("configservice.user", "alice").unwrap();
+    b.insert("configservice.user", "bob").unwrap();
+    loop {
+        let (got_a, got_b) = (a.get("configservice.user"), b.get("configservice.user"));
+        if got_a == got_b {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn late_servers_get_the_state() {
+    let network = MemoryNetwork::new();
+    let first = crdt();
+    serve(&network, "server-a", &first);
+    first.insert("configservice.port", "61250").unwrap();
+
+    let late = crdt();
+    serve(&network, "server-b", &late);
+    settle(&late, "configservice.port", Some("61250")).await;
+}
+
+#[tokio::test]
+async fn clients_use_the_same_api() {
+    let network = MemoryNetwork::new();
+    let (a, b) = (crdt(), crdt());
+    serve(&network, "server-a", &a);
+    serve(&network, "server-b", &b);
+    let client = Client::builder().build(network.join("client"));
+
+    client.set("configservice.port", "61250").await.unwrap();
+    assert_eq!(client.get("configservice.port").await.unwrap(), "61250");
+    settle(&a, "configservice.port", Some("61250")).await;
+    settle(&b, "configservice.port", Some("61250")).await;
+
+    match client.watch("configservice.port").await {
+        Err(magnetite::Error::Refused(Refusal::Unsupported(_))) => {}
+        other => panic!("expected an unsupported watch, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn large_maps_are_gossiped_in_parts() {
+    // gossipsub's default transmit limit
+    let network = MemoryNetwork::new().max_message_size(64 * 1024);
+    let a = crdt();
+    serve(&network, "server-a", &a);
+    let banner = "x".repeat(1000);
+    for i in 0..300 {
+        a.insert(format!("banner.{:03}", i), banner.as_str())
+            .unwrap();
+    }
+    // gossiped before the late server joins
+    sleep(Duration::from_millis(200)).await;
+
+    let b = crdt();
+    serve(&network, "server-b", &b);
+    settle(&b, "banner.299", Some(&banner)).await;
+    assert_eq!(b.len(), 300);
+}
+
+#[tokio::test]
+async fn old_tombstones_are_forgotten() {
+    let network = MemoryNetwork::new();
+    let a = Storage::default().crdt(
+        Crdt::default()
+            .gossip_every(Duration::from_millis(50))
+            .sync_timeout(Duration::from_millis(100))
+            .tombstone_ttl(Duration::from_millis(100)),
+    );
+    serve(&network, "server-a", &a);
+    a.insert("configservice.address", "localhost").unwrap();
+    a.remove("configservice.address").unwrap();
+    sleep(Duration::from_millis(300)).await;
+
+    // without the tombstone nothing tells the stale server of the delete
+    let stale = crdt();
+    stale
+        .seed([("configservice.address", "localhost")])
+        .unwrap();
+    serve(&network, "server-b", &stale);
+    settle(&a, "configservice.address", Some("localhost")).await;
+}
+
+/// The gossip servers send each other, as far as the tests forge it.
+#[derive(Serialize)]
+enum Gossip {
+    Updates(Vec<Update>),
+    Digest(HashMap<PeerId, u64>),
+}
+
+#[derive(Serialize)]
+struct Update {
+    key: String,
+    value: Option<Value>,
+    stamp: Stamp,
+}
+
+/// Gossips `gossip` from a peer of its own, claiming to be `sender`.
+async fn forge(network: &MemoryNetwork, sender: &str, gossip: &Gossip) {
+    let message = Message {
+        id: RequestIds::new(PeerId::new("mallory")).next_id(),
+        msgtype: MsgType::Control,
+        payload: Format::MessagePack.encode(gossip).unwrap(),
+    };
+    let forged = Envelope::new(PeerId::new(sender), message)
+        .encode(&Format::MessagePack)
+        .unwrap();
+    network
+        .join("mallory")
+        .publish(&Topics::default().replication(), forged)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn writes_from_the_future_are_dropped() {
+    let network = MemoryNetwork::new();
+    let (a, b) = (crdt(), crdt());
+    serve(&network, "server-a", &a);
+    serve(&network, "server-b", &b);
+    a.insert("configservice.port", "61250").unwrap();
+    settle(&b, "configservice.port", Some("61250")).await;
+
+    let gossip = Gossip::Updates(vec![Update {
+        key: "configservice.port".to_owned(),
+        value: Some("6666".into()),
+        stamp: Stamp {
+            millis: u64::MAX,
+            node: PeerId::new("mallory"),
+        },
+    }]);
+    forge(&network, "mallory", &gossip).await;
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(a.get("configservice.port").as_deref(), Some("61250"));
+
+    // later writes still win
+    a.insert("configservice.port", "61251").unwrap();
+    settle(&b, "configservice.port", Some("61251")).await;
+}
+
+#[tokio::test]
+async fn digests_are_answered_to_their_peer() {
+    let network = MemoryNetwork::new();
+    let waiting = Storage::default().crdt(Crdt::default().sync_timeout(Duration::from_secs(60)));
+    serve(&network, "server-b", &waiting);
+    // its own digest went unanswered
+    sleep(Duration::from_millis(100)).await;
+    let a = crdt();
+    serve(&network, "server-a", &a);
+    a.insert("configservice.port", "61250").unwrap();
+    settle(&waiting, "configservice.port", Some("61250")).await;
+    let ready = async {
+        while !a.is_ready() {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), ready)
+        .await
+        .expect("server-a never got ready");
+
+    // a digest claiming to come from the waiting server
+    forge(&network, "server-b", &Gossip::Digest(HashMap::new())).await;
+    sleep(Duration::from_millis(200)).await;
+    assert!(!waiting.is_ready());
+}
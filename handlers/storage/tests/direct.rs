@@ -0,0 +1,28 @@
+use magnetite::{Client, PeerId, Service};
+use magnetite_memory::MemoryNetwork;
+use magnetite_storage::Storage;
+
+#[tokio::test]
+async fn direct_requests_reach_only_their_peer() {
+    let network = MemoryNetwork::new();
+    let mut servers = Vec::new();
+    for (name, port) in [("server-a", "61250"), ("server-b", "61251")] {
+        let storage = Storage::default();
+        storage.insert("configservice.port", port).unwrap();
+        Service::builder()
+            .handler(storage.clone())
+            .build(network.join(name))
+            .spawn();
+        servers.push(storage);
+    }
+    let client = Client::builder().build(network.join("client"));
+
+    let server = client.direct(PeerId::new("server-b"));
+    assert_eq!(server.get("configservice.port").await.unwrap(), "61251");
+    server.set("configservice.user", "admin").await.unwrap();
+    let server = client.direct(PeerId::new("server-a"));
+    assert_eq!(server.get("configservice.port").await.unwrap(), "61250");
+
+    assert!(servers[0].get("configservice.user").is_none());
+    assert_eq!(servers[1].len(), 2);
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use magnetite_storage::{LogEngine, Storage};
+
+use common::start;
+
+#[tokio::test]
+async fn writes_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let version = {
+        let storage = Storage::open(dir.path()).unwrap();
+        let client = start(&storage);
+        client.set("configservice.port", "61251").await.unwrap()
+    };
+
+    let storage = Storage::open(dir.path()).unwrap();
+    let got = storage.get_versioned("configservice.port").unwrap();
+    assert_eq!(got.value, "61251");
+    assert_eq!(got.version, version);
+
+    let client = start(&storage);
+    assert!(client.set("configservice.port", "61252").await.unwrap() > version);
+}
+
+#[test]
+fn deletes_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let storage = Storage::open(dir.path()).unwrap();
+        storage
+            .seed(vec![
+                ("configservice.user", "public"),
+                ("logservice.level", "debug"),
+            ])
+            .unwrap();
+        storage.remove("logservice.level").unwrap();
+    }
+
+    let storage = Storage::open(dir.path()).unwrap();
+    assert_eq!(storage.get("configservice.user").as_deref(), Some("public"));
+    assert_eq!(storage.get("logservice.level"), None);
+}
+
+#[test]
+fn snapshots_replace_the_log() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let engine = LogEngine::open(dir.path()).unwrap().snapshot_every(3);
+        let storage = Storage::with_engine(engine);
+        for port in 0..10 {
+            storage
+                .insert(format!("service{}.port", port), port.to_string())
+                .unwrap();
+        }
+    }
+    assert!(dir.path().join("snapshot").exists());
+
+    let storage = Storage::open(dir.path()).unwrap();
+    assert_eq!(storage.len(), 10);
+    assert_eq!(storage.get("service9.port").as_deref(), Some("9"));
+}
+
+#[test]
+fn torn_log_tail_is_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let storage = Storage::open(dir.path()).unwrap();
+        storage.insert("configservice.port", "61250").unwrap();
+    }
+    let mut log = OpenOptions::new()
+        .append(true)
+        .open(dir.path().join("log"))
+        .unwrap();
+    log.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+
+    let storage = Storage::open(dir.path()).unwrap();
+    assert_eq!(storage.get("configservice.port").as_deref(), Some("61250"));
+    storage.insert("configservice.user", "public").unwrap();
+    drop(storage);
+
+    let storage = Storage::open(dir.path()).unwrap();
+    assert_eq!(storage.len(), 2);
+}
+
+#[test]
+fn failed_snapshots_keep_the_batch() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        // the snapshot can't be written over a directory
+        std::fs::create_dir(dir.path().join("snapshot.tmp")).unwrap();
+        let engine = LogEngine::open(dir.path()).unwrap().snapshot_every(1);
+        let storage = Storage::with_engine(engine);
+        storage.insert("configservice.port", "61250").unwrap();
+        storage.insert("configservice.user", "public").unwrap();
+    }
+    assert!(!dir.path().join("snapshot").exists());
+
+    let storage = Storage::open(dir.path()).unwrap();
+    assert_eq!(storage.len(), 2);
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use magnetite::{Error, ListRequest, Refusal};
+use magnetite_storage::{Quota, Storage};
+
+use common::start;
+
+#[tokio::test]
+async fn keyspaces_keep_keys_apart() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let billing = client.in_keyspace("billing");
+    let search = client.in_keyspace("search");
+
+    client.set("port", "61250").await.unwrap();
+    billing.set("port", "8080").await.unwrap();
+    search.set("port", "9200").await.unwrap();
+    search.set("replicas", "3").await.unwrap();
+
+    assert_eq!(client.get("port").await.unwrap(), "61250");
+    assert_eq!(billing.get("port").await.unwrap(), "8080");
+    assert!(billing.get("replicas").await.unwrap_err().is_not_found());
+
+    assert_eq!(client.list_all("").await.unwrap(), ["port"]);
+    assert_eq!(search.list_all("").await.unwrap(), ["port", "replicas"]);
+    let page = search.list(ListRequest::new("").limit(1)).await.unwrap();
+    assert_eq!(page.next.as_deref(), Some("port"));
+
+    assert_eq!(storage.snapshot().len(), 1);
+    assert_eq!(storage.len(), 4);
+}
+
+#[tokio::test]
+async fn empty_keys_are_listed_before_the_others() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    client.in_keyspace("billing").set("", "8080").await.unwrap();
+    client.set("", "61250").await.unwrap();
+    client.set("port", "61250").await.unwrap();
+
+    assert_eq!(client.list_all("").await.unwrap(), ["", "port"]);
+    assert_eq!(client.dump().await.unwrap().entries.len(), 2);
+    assert_eq!(storage.snapshot()[""], "61250");
+    let billing = client.in_keyspace("billing");
+    assert_eq!(billing.list_all("").await.unwrap(), [""]);
+}
+
+#[tokio::test]
+async fn quotas_refuse_writes_past_them() {
+    let storage = Storage::default().quota("tenant", Quota::default().max_keys(2));
+    let client = start(&storage);
+    let tenant = client.in_keyspace("tenant");
+
+    tenant.set("a", "1").await.unwrap();
+    tenant.set("b", "2").await.unwrap();
+    tenant.set("b", "3").await.unwrap();
+    match tenant.set("c", "4").await {
+        Err(Error::Refused(Refusal::OverQuota { keyspace })) => assert_eq!(keyspace, "tenant"),
+        other => panic!("expected the quota to refuse, got {:?}", other),
+    }
+    // other keyspaces have their own limits
+    client.in_keyspace("other").set("c", "4").await.unwrap();
+    client.set("c", "4").await.unwrap();
+}
+
+#[tokio::test]
+async fn byte_quotas_count_keys_and_values() {
+    let storage = Storage::default().quota("tenant", Quota::default().max_bytes(8));
+    let tenant = start(&storage).in_keyspace("tenant");
+
+    tenant.set("key", "value").await.unwrap();
+    assert!(tenant.set("key", "longer").await.is_err());
+    tenant.set("key", "v").await.unwrap();
+    tenant.set("k", "v").await.unwrap();
+}
+
+#[tokio::test]
+async fn quotas_follow_deletes_and_wipes() {
+    let storage = Storage::default().quota("tenant", Quota::default().max_keys(2));
+    let tenant = start(&storage).in_keyspace("tenant");
+
+    tenant.set("a", "1").await.unwrap();
+    tenant.set("b", "2").await.unwrap();
+    assert!(tenant.set("c", "3").await.is_err());
+    tenant.delete("a").await.unwrap();
+    tenant.set("c", "3").await.unwrap();
+    assert!(tenant.set("d", "4").await.is_err());
+
+    storage.wipe("tenant").unwrap();
+    tenant.set("d", "4").await.unwrap();
+    tenant.set("e", "5").await.unwrap();
+    assert!(tenant.set("f", "6").await.is_err());
+}
+
+#[tokio::test]
+async fn dump_and_wipe_a_keyspace() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let tenant = client.in_keyspace("tenant");
+    tenant.set("a", "1").await.unwrap();
+    tenant.set("b", "2").await.unwrap();
+    client.in_keyspace("other").set("a", "1").await.unwrap();
+
+    let dump = tenant.dump().await.unwrap();
+    assert_eq!(dump.entries.len(), 2);
+    assert_eq!(dump.entries["b"].value, "2");
+
+    assert_eq!(tenant.wipe().await.unwrap(), 2);
+    assert!(tenant.dump().await.unwrap().entries.is_empty());
+    assert_eq!(client.in_keyspace("other").get("a").await.unwrap(), "1");
+
+    assert!(matches!(
+        client.wipe().await,
+        Err(Error::Refused(Refusal::Invalid(_)))
+    ));
+    assert_eq!(storage.wipe("other").unwrap(), 1);
+}
+
+#[tokio::test]
+async fn bad_names_are_refused() {
+    let storage = Storage::default();
+    let client = start(&storage);
+
+    assert!(matches!(
+        client.in_keyspace("a\0b").get("key").await,
+        Err(Error::Refused(Refusal::Invalid(_)))
+    ));
+    assert!(matches!(
+        client.set("\0tenant\0key", "value").await,
+        Err(Error::Refused(Refusal::Invalid(_)))
+    ));
+}
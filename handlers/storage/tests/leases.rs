@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use magnetite::{Client, Error, Refusal, Service, SetRequest};
+use magnetite_memory::MemoryNetwork;
+use magnetite_storage::Storage;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+/// Serves `storage` ticking often enough to expire keys on time.
+fn start(storage: &Storage) -> Client {
+    let network = MemoryNetwork::new();
+    serve(&network, storage);
+    Client::builder().build(network.join("client"))
+}
+
+fn serve(network: &MemoryNetwork, storage: &Storage) -> JoinHandle<magnetite::Result<()>> {
+    Service::builder()
+        .tick(Duration::from_millis(20))
+        .handler(storage.clone())
+        .build(network.join("server"))
+        .spawn()
+}
+
+async fn gone(storage: &Storage, key: &str) {
+    let expired = async {
+        while storage.get(key).is_some() {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), expired)
+        .await
+        .unwrap_or_else(|_| panic!("`{}` never expired", key));
+}
+
+#[tokio::test]
+async fn keys_expire_after_their_ttl() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let mut changes = client.watch("session").await.unwrap();
+
+    let ttl = Duration::from_millis(200);
+    client
+        .send_set(SetRequest::new("session", "alive").ttl(ttl))
+        .await
+        .unwrap();
+    client.set("config", "kept").await.unwrap();
+    assert_eq!(client.get("session").await.unwrap(), "alive");
+
+    sleep(Duration::from_millis(400)).await;
+    assert!(client.get("session").await.unwrap_err().is_not_found());
+    assert_eq!(client.get("config").await.unwrap(), "kept");
+
+    changes.next().await.unwrap();
+    let change = changes.next().await.unwrap();
+    assert_eq!(change.old.unwrap().value, "alive");
+    assert_eq!(change.new, None);
+}
+
+#[tokio::test]
+async fn expired_keys_are_not_read_before_the_tick() {
+    let storage = Storage::default();
+    let network = MemoryNetwork::new();
+    Service::builder()
+        .tick(Duration::from_secs(60))
+        .handler(storage.clone())
+        .build(network.join("server"))
+        .spawn();
+    let client = Client::builder().build(network.join("client"));
+
+    let ttl = Duration::from_millis(100);
+    client
+        .send_set(SetRequest::new("session", "alive").ttl(ttl))
+        .await
+        .unwrap();
+    client.set("config", "kept").await.unwrap();
+    sleep(Duration::from_millis(200)).await;
+
+    assert!(client.get("session").await.unwrap_err().is_not_found());
+    assert!(!client.exists("session").await.unwrap());
+    assert_eq!(client.list_all("").await.unwrap(), ["config"]);
+    assert_eq!(storage.get("session"), None);
+    // still stored until the tick
+    assert_eq!(storage.len(), 2);
+}
+
+#[tokio::test]
+async fn setting_again_without_ttl_keeps_the_key() {
+    let storage = Storage::default();
+    let client = start(&storage);
+
+    let ttl = Duration::from_millis(100);
+    client
+        .send_set(SetRequest::new("key", "temporary").ttl(ttl))
+        .await
+        .unwrap();
+    client.set("key", "permanent").await.unwrap();
+
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(storage.get("key").as_deref(), Some("permanent"));
+}
+
+#[tokio::test]
+async fn leased_keys_live_while_kept_alive() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let lease = client.grant(Duration::from_millis(300)).await.unwrap();
+    client
+        .send_set(SetRequest::new("services.config", "/ip4/127.0.0.1").lease(lease.clone()))
+        .await
+        .unwrap();
+
+    for _ in 0..5 {
+        sleep(Duration::from_millis(100)).await;
+        client.keep_alive(&lease).await.unwrap();
+    }
+    assert!(storage.get("services.config").is_some());
+
+    // the service stops renewing
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(storage.get("services.config"), None);
+    match client.keep_alive(&lease).await {
+        Err(Error::Refused(Refusal::LeaseExpired { lease: expired })) => {
+            assert_eq!(expired, lease)
+        }
+        other => panic!("expected the lease to be expired, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn revoking_deletes_the_leased_keys() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let lease = client.grant(Duration::from_secs(60)).await.unwrap();
+    for key in ["a", "b"] {
+        client
+            .send_set(SetRequest::new(key, "leased").lease(lease.clone()))
+            .await
+            .unwrap();
+    }
+    client
+        .in_keyspace("tenant")
+        .send_set(SetRequest::new("c", "leased").lease(lease.clone()))
+        .await
+        .unwrap();
+    client.set("d", "kept").await.unwrap();
+
+    assert_eq!(client.revoke(&lease).await.unwrap(), 3);
+    assert_eq!(storage.len(), 1);
+    assert_eq!(storage.get("d").as_deref(), Some("kept"));
+
+    let refused = client
+        .send_set(SetRequest::new("e", "late").lease(lease))
+        .await;
+    assert!(matches!(
+        refused,
+        Err(Error::Refused(Refusal::LeaseExpired { .. }))
+    ));
+}
+
+#[tokio::test]
+async fn overlong_ttls_are_refused() {
+    let storage = Storage::default();
+    let client = start(&storage);
+
+    let refused = client
+        .send_set(SetRequest::new("session", "alive").ttl(Duration::MAX))
+        .await;
+    assert!(matches!(refused, Err(Error::Refused(Refusal::Invalid(_)))));
+    assert!(matches!(
+        client.grant(Duration::MAX).await,
+        Err(Error::Refused(Refusal::Invalid(_)))
+    ));
+    assert!(storage.is_empty());
+}
+
+#[tokio::test]
+async fn expiry_survives_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let storage = Storage::open(dir.path()).unwrap();
+        let network = MemoryNetwork::new();
+        let service = serve(&network, &storage);
+        let client = Client::builder().build(network.join("client"));
+        let ttl = Duration::from_millis(300);
+        client
+            .send_set(SetRequest::new("session", "alive").ttl(ttl))
+            .await
+            .unwrap();
+        let lease = client.grant(ttl).await.unwrap();
+        client
+            .send_set(SetRequest::new("services.config", "/ip4/127.0.0.1").lease(lease))
+            .await
+            .unwrap();
+        client.set("config", "kept").await.unwrap();
+        service.abort();
+        let _ = service.await;
+    }
+
+    let storage = Storage::open(dir.path()).unwrap();
+    assert_eq!(storage.len(), 3);
+    serve(&MemoryNetwork::new(), &storage);
+    gone(&storage, "session").await;
+    gone(&storage, "services.config").await;
+    assert_eq!(storage.get("config").as_deref(), Some("kept"));
+    // and deleted on a tick
+    let deleted = async {
+        while storage.len() > 1 {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), deleted)
+        .await
+        .expect("the expired keys were never deleted");
+}
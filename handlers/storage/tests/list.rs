@@ -0,0 +1,75 @@
+mod common;
+
+use magnetite::ListRequest;
+use magnetite_storage::Storage;
+
+use common::start;
+
+fn configservice() -> Storage {
+    let storage = Storage::default();
+    storage
+        .seed(vec![
+            ("configservice.address", "localhost"),
+            ("configservice.port", "61250"),
+            ("configservice.user", "public"),
+            ("logservice.level", "debug"),
+        ])
+        .unwrap();
+    storage
+}
+
+#[tokio::test]
+async fn list_filters_by_prefix_in_order() {
+    let client = start(&configservice());
+
+    let page = client
+        .list(ListRequest::new("configservice."))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        page.keys,
+        vec![
+            "configservice.address",
+            "configservice.port",
+            "configservice.user"
+        ]
+    );
+    assert_eq!(page.next, None);
+}
+
+#[tokio::test]
+async fn list_pages_through_every_key() {
+    let client = start(&configservice());
+
+    let first = client
+        .list(ListRequest::new("configservice.").limit(2))
+        .await
+        .unwrap();
+    assert_eq!(
+        first.keys,
+        vec!["configservice.address", "configservice.port"]
+    );
+
+    let cursor = first.next.expect("a second page");
+    let second = client
+        .list(ListRequest::new("configservice.").limit(2).cursor(cursor))
+        .await
+        .unwrap();
+    assert_eq!(second.keys, vec!["configservice.user"]);
+    assert_eq!(second.next, None);
+
+    assert_eq!(client.list_all("").await.unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn exists_and_delete() {
+    let storage = configservice();
+    let client = start(&storage);
+
+    assert!(client.exists("logservice.level").await.unwrap());
+    assert!(client.delete("logservice.level").await.unwrap());
+    assert!(!client.exists("logservice.level").await.unwrap());
+    assert!(!client.delete("logservice.level").await.unwrap());
+    assert_eq!(storage.get("logservice.level"), None);
+}
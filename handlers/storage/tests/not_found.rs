@@ -0,0 +1,69 @@
+mod common;
+
+use std::time::Duration;
+
+use magnetite::{Client, RetryPolicy};
+use magnetite_storage::Storage;
+
+use common::{serve, start};
+
+#[tokio::test]
+async fn missing_key_is_not_found() {
+    let client = start(&Storage::default());
+
+    let err = client.get("configservice.port").await.unwrap_err();
+
+    assert!(err.is_not_found());
+    assert_eq!(
+        client.get_or("configservice.port", "61250").await.unwrap(),
+        "61250"
+    );
+}
+
+#[tokio::test]
+async fn none_is_an_ordinary_value() {
+    let storage = Storage::default();
+    storage.insert("configservice.user", "NONE").unwrap();
+    let client = start(&storage);
+
+    assert_eq!(client.get("configservice.user").await.unwrap(), "NONE");
+    assert_eq!(
+        client.get_or("configservice.user", "public").await.unwrap(),
+        "NONE"
+    );
+}
+
+#[tokio::test]
+async fn wanted_key_falls_back_to_its_default() {
+    let storage = Storage::default();
+    storage
+        .insert("configservice.address", "localhost")
+        .unwrap();
+    let client = Client::builder()
+        .want("configservice.address")
+        .want_or("configservice.port", "61250")
+        .build(serve(&storage).join("client"));
+
+    let config = client.ready().await.unwrap();
+
+    assert_eq!(config["configservice.address"], "localhost");
+    assert_eq!(config["configservice.port"], "61250");
+}
+
+#[tokio::test]
+async fn wanted_key_waits_until_it_exists() {
+    let network = serve(&Storage::default());
+    let waiting = Client::builder()
+        .want("configservice.port")
+        .retry(RetryPolicy::forever(Duration::from_millis(10)))
+        .build(network.join("waiting"));
+    let writer = Client::builder().build(network.join("writer"));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(waiting.wanted()["configservice.port"], None);
+
+    writer.set("configservice.port", "61250").await.unwrap();
+    let config = waiting.ready().await.unwrap();
+
+    assert_eq!(config["configservice.port"], "61250");
+}
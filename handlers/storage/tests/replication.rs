@@ -0,0 +1,272 @@
+use std::time::Duration;
+
+use magnetite::request::RequestIds;
+use magnetite::{
+    Backend, Client, Codec, Envelope, Format, Message, MsgType, PeerId, Service, SetRequest,
+    Topics, Versioned,
+};
+use magnetite_memory::MemoryNetwork;
+use magnetite_storage::engine::{Batch, Op};
+use magnetite_storage::{Replication, Storage};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+fn replicated() -> Storage {
+    Storage::default().replicate(Replication::default().timeout(Duration::from_millis(150)))
+}
+
+fn serve(
+    network: &MemoryNetwork,
+    name: &str,
+    storage: &Storage,
+) -> JoinHandle<magnetite::Result<()>> {
+    Service::builder()
+        .tick(Duration::from_millis(20))
+        .handler(storage.clone())
+        .build(network.join(name))
+        .spawn()
+}
+
+/// Waits until exactly one of `storages` leads, returns its index.
+async fn leader(storages: &[&Storage]) -> usize {
+    let elected = async {
+        loop {
+            let leading: Vec<usize> = (0..storages.len())
+                .filter(|i| storages[*i].is_leader())
+                .collect();
+            if let [leader] = leading[..] {
+                return leader;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), elected)
+        .await
+        .expect("no single leader was elected")
+}
+
+async fn settle(storage: &Storage, key: &str, value: &str) {
+    let settled = async {
+        while storage.get(key).as_deref() != Some(value) {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), settled)
+        .await
+        .unwrap_or_else(|_| panic!("`{}` never became `{}`", key, value));
+}
+
+#[tokio::test]
+async fn writes_reach_the_followers() {
+    let network = MemoryNetwork::new();
+    let storages = [replicated(), replicated(), replicated()];
+    for (i, storage) in storages.iter().enumerate() {
+        serve(&network, &format!("server-{}", i), storage);
+    }
+    let storages: Vec<&Storage> = storages.iter().collect();
+    let leader = leader(&storages).await;
+
+    let client = Client::builder().build(network.join("client"));
+    let version = client.set("configservice.port", "61250").await.unwrap();
+
+    for storage in &storages {
+        settle(storage, "configservice.port", "61250").await;
+        let got = storage.get_versioned("configservice.port").unwrap();
+        assert_eq!(got.version, version);
+    }
+    assert!(storages[leader].is_leader());
+}
+
+#[tokio::test]
+async fn late_servers_catch_up() {
+    let network = MemoryNetwork::new();
+    let first = replicated();
+    serve(&network, "server-0", &first);
+    leader(&[&first]).await;
+    first.insert("configservice.port", "61250").unwrap();
+
+    let late = replicated();
+    serve(&network, "server-1", &late);
+    settle(&late, "configservice.port", "61250").await;
+}
+
+#[tokio::test]
+async fn survives_the_leader() {
+    let network = MemoryNetwork::new();
+    let storages = [replicated(), replicated(), replicated()];
+    let mut services: Vec<_> = storages
+        .iter()
+        .enumerate()
+        .map(|(i, storage)| Some(serve(&network, &format!("server-{}", i), storage)))
+        .collect();
+    let all: Vec<&Storage> = storages.iter().collect();
+    let old = leader(&all).await;
+
+    let client = Client::builder().build(network.join("client"));
+    client.set("configservice.port", "61250").await.unwrap();
+    for storage in &all {
+        settle(storage, "configservice.port", "61250").await;
+    }
+
+    services[old].take().unwrap().abort();
+    let alive: Vec<&Storage> = (0..all.len())
+        .filter(|i| *i != old)
+        .map(|i| all[i])
+        .collect();
+    leader(&alive).await;
+
+    assert_eq!(client.get("configservice.port").await.unwrap(), "61250");
+    client.set("configservice.user", "public").await.unwrap();
+    for storage in &alive {
+        settle(storage, "configservice.user", "public").await;
+    }
+}
+
+#[tokio::test]
+async fn followers_ahead_of_the_leader_resync() {
+    let network = MemoryNetwork::new();
+    let storages = [replicated(), replicated()];
+    for (i, storage) in storages.iter().enumerate() {
+        serve(&network, &format!("server-{}", i), storage);
+    }
+    let all: Vec<&Storage> = storages.iter().collect();
+    let leader = leader(&all).await;
+    let follower = all[1 - leader];
+
+    follower
+        .insert("configservice.address", "localhost")
+        .unwrap();
+    let client = Client::builder().build(network.join("client"));
+    client.set("configservice.port", "61250").await.unwrap();
+
+    settle(follower, "configservice.port", "61250").await;
+    let resynced = async {
+        while follower.get("configservice.address").is_some() {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), resynced)
+        .await
+        .expect("the follower kept its own write");
+    assert_eq!(follower.snapshot(), all[leader].snapshot());
+    assert_eq!(
+        format!("{:?}", follower),
+        format!("{:?}", all[leader]),
+        "revisions differ"
+    );
+}
+
+#[tokio::test]
+async fn large_storages_are_synced_in_parts() {
+    // gossipsub's default transmit limit
+    let network = MemoryNetwork::new().max_message_size(64 * 1024);
+    let first = replicated();
+    serve(&network, "server-0", &first);
+    leader(&[&first]).await;
+    let banner = "x".repeat(1000);
+    first
+        .seed((0..300).map(|i| (format!("banner.{:03}", i), banner.as_str())))
+        .unwrap();
+
+    let late = replicated();
+    serve(&network, "server-1", &late);
+    settle(&late, "banner.299", &banner).await;
+    assert_eq!(late.len(), 300);
+}
+
+#[tokio::test]
+async fn a_new_leader_expires_the_keys() {
+    let network = MemoryNetwork::new();
+    let storages = [replicated(), replicated(), replicated()];
+    let mut services: Vec<_> = storages
+        .iter()
+        .enumerate()
+        .map(|(i, storage)| Some(serve(&network, &format!("server-{}", i), storage)))
+        .collect();
+    let all: Vec<&Storage> = storages.iter().collect();
+    let old = leader(&all).await;
+
+    let client = Client::builder().build(network.join("client"));
+    let ttl = Duration::from_millis(500);
+    client
+        .send_set(SetRequest::new("session", "alive").ttl(ttl))
+        .await
+        .unwrap();
+    let lease = client.grant(ttl).await.unwrap();
+    client
+        .send_set(SetRequest::new("services.config", "/ip4/127.0.0.1").lease(lease))
+        .await
+        .unwrap();
+    for storage in &all {
+        settle(storage, "services.config", "/ip4/127.0.0.1").await;
+    }
+
+    services[old].take().unwrap().abort();
+    let alive: Vec<&Storage> = (0..all.len())
+        .filter(|i| *i != old)
+        .map(|i| all[i])
+        .collect();
+    leader(&alive).await;
+    let expired = async {
+        while alive.iter().any(|storage| !storage.is_empty()) {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), expired)
+        .await
+        .expect("the new leader kept the keys");
+}
+
+/// What the leader tells the followers, as far as the tests forge it.
+#[derive(Serialize)]
+enum Replicate {
+    Batch { term: u64, prev: u64, batch: Batch },
+}
+
+#[tokio::test]
+async fn followers_only_take_batches_from_the_leader() {
+    let network = MemoryNetwork::new();
+    let (leading, follower) = (replicated(), replicated());
+    serve(&network, "server-0", &leading);
+    leader(&[&leading]).await;
+    leading.insert("configservice.port", "61250").unwrap();
+    serve(&network, "server-1", &follower);
+    settle(&follower, "configservice.port", "61250").await;
+
+    // a batch claiming to come from the leader
+    let mut mallory = network.join("mallory");
+    let revision = follower
+        .get_versioned("configservice.port")
+        .unwrap()
+        .version;
+    let forged = Replicate::Batch {
+        term: 1,
+        prev: revision,
+        batch: Batch {
+            revision: revision + 1,
+            ops: vec![Op::Put {
+                key: "configservice.port".to_owned(),
+                value: Versioned {
+                    value: "6666".into(),
+                    version: revision + 1,
+                },
+            }],
+        },
+    };
+    let message = Message {
+        id: RequestIds::new(PeerId::new("mallory")).next_id(),
+        msgtype: MsgType::Control,
+        payload: Format::MessagePack.encode(&forged).unwrap(),
+    };
+    let forged = Envelope::new(PeerId::new("server-0"), message)
+        .encode(&Format::MessagePack)
+        .unwrap();
+    mallory
+        .publish(&Topics::default().replication(), forged)
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(follower.get("configservice.port").as_deref(), Some("61250"));
+}
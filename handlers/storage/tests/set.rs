@@ -0,0 +1,49 @@
+mod common;
+
+use magnetite_storage::Storage;
+
+use common::start;
+
+#[tokio::test]
+async fn get_after_set_returns_the_new_value() {
+    let storage = Storage::default();
+    storage.insert("configservice.port", "61250").unwrap();
+    let client = start(&storage);
+
+    client.set("configservice.port", "61251").await.unwrap();
+
+    assert_eq!(client.get("configservice.port").await.unwrap(), "61251");
+    assert_eq!(storage.get("configservice.port").as_deref(), Some("61251"));
+}
+
+#[tokio::test]
+async fn set_creates_missing_keys() {
+    let storage = Storage::default();
+    let client = start(&storage);
+
+    client.set("configservice.user", "public").await.unwrap();
+    client
+        .set("configservice.address", "localhost")
+        .await
+        .unwrap();
+
+    assert_eq!(client.get("configservice.user").await.unwrap(), "public");
+    assert_eq!(
+        client.get("configservice.address").await.unwrap(),
+        "localhost"
+    );
+    assert_eq!(storage.snapshot().len(), 2);
+}
+
+#[tokio::test]
+async fn set_only_touches_its_own_key() {
+    let storage = Storage::default();
+    storage.seed(vec![("a", "b"), ("b", "c")]).unwrap();
+    let client = start(&storage);
+
+    client.set("a", "z").await.unwrap();
+
+    assert_eq!(client.get("a").await.unwrap(), "z");
+    assert_eq!(client.get("b").await.unwrap(), "c");
+    assert_eq!(storage.get("z"), None);
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use magnetite::Service;
+use magnetite_memory::MemoryNetwork;
+use magnetite_storage::{Crdt, Replication, Storage};
+use tokio::time::{sleep, timeout};
+
+/// Only syncing gets writes across, gossip and the sync timeout are far off.
+fn crdt() -> Storage {
+    Storage::default().crdt(
+        Crdt::default()
+            .gossip_every(Duration::from_secs(3600))
+            .sync_timeout(Duration::from_secs(3600)),
+    )
+}
+
+fn serve(network: &MemoryNetwork, name: &str, storage: &Storage) {
+    Service::builder()
+        .tick(Duration::from_millis(20))
+        .handler(storage.clone())
+        .build(network.join(name))
+        .spawn();
+}
+
+async fn ready(storage: &Storage) {
+    let wait = async {
+        while !storage.is_ready() {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+    timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("storage never got ready");
+}
+
+#[tokio::test]
+async fn lone_crdt_waits_for_the_sync_timeout() {
+    let network = MemoryNetwork::new();
+    let storage = crdt();
+    serve(&network, "server", &storage);
+    sleep(Duration::from_millis(200)).await;
+    assert!(!storage.is_ready());
+}
+
+#[tokio::test]
+async fn late_crdt_pulls_what_it_missed() {
+    let network = MemoryNetwork::new();
+    let first = Storage::default().crdt(Crdt::default().sync_timeout(Duration::ZERO));
+    serve(&network, "server-a", &first);
+    ready(&first).await;
+    first.insert("configservice.port", "61251").unwrap();
+    first.insert("configservice.user", "admin").unwrap();
+    first.remove("configservice.user").unwrap();
+
+    // a fresh server comes up with stale defaults
+    let late = crdt();
+    late.seed(vec![
+        ("configservice.port", "61250"),
+        ("configservice.user", "public"),
+    ])
+    .unwrap();
+    serve(&network, "server-b", &late);
+    ready(&late).await;
+
+    assert_eq!(late.get("configservice.port").as_deref(), Some("61251"));
+    assert_eq!(late.get("configservice.user"), None);
+}
+
+#[tokio::test]
+async fn late_follower_catches_up_with_the_leader() {
+    let network = MemoryNetwork::new();
+    let config = Replication::default().timeout(Duration::from_millis(150));
+    let first = Storage::default().replicate(config.clone());
+    serve(&network, "server-a", &first);
+    ready(&first).await;
+    assert!(first.is_leader());
+    first.insert("configservice.port", "61251").unwrap();
+
+    let late = Storage::default().replicate(config);
+    serve(&network, "server-b", &late);
+    ready(&late).await;
+    assert_eq!(late.get("configservice.port").as_deref(), Some("61251"));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use magnetite::{Error, Kind, Value};
+use magnetite_storage::Storage;
+use serde::{Deserialize, Serialize};
+
+use common::start;
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct Limits {
+    connections: u32,
+    hosts: Vec<String>,
+}
+
+#[tokio::test]
+async fn values_keep_their_type() {
+    let storage = Storage::default();
+    let client = start(&storage);
+
+    client.set("port", 61250).await.unwrap();
+    client.set("enabled", true).await.unwrap();
+    client.set("cert", vec![0x30, 0x82, 0xff]).await.unwrap();
+    client.set("user", "public").await.unwrap();
+
+    assert_eq!(client.get_as::<u16>("port").await.unwrap(), 61250);
+    assert_eq!(client.get_as::<i64>("port").await.unwrap(), 61250);
+    assert!(client.get_as::<bool>("enabled").await.unwrap());
+    assert_eq!(
+        client.get_as::<Vec<u8>>("cert").await.unwrap(),
+        [0x30, 0x82, 0xff]
+    );
+    assert_eq!(client.get_as::<String>("user").await.unwrap(), "public");
+
+    let cert = client.get_value("cert").await.unwrap();
+    assert_eq!(cert.kind(), Kind::Bytes);
+    assert_eq!(storage.get_value("cert"), Some(cert));
+}
+
+#[tokio::test]
+async fn get_writes_out_text() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    client.set("port", 61250).await.unwrap();
+    client.set("enabled", false).await.unwrap();
+    client.set("cert", vec![0xff]).await.unwrap();
+
+    assert_eq!(client.get("port").await.unwrap(), "61250");
+    assert_eq!(client.get("enabled").await.unwrap(), "false");
+    assert!(matches!(client.get("cert").await, Err(Error::Decode(_))));
+    assert_eq!(storage.get("cert"), None);
+}
+
+#[tokio::test]
+async fn mismatched_types_fail_to_decode() {
+    let client = start(&Storage::default());
+    client.set("port", "61250").await.unwrap();
+    client.set("big", 1 << 20).await.unwrap();
+
+    assert!(matches!(
+        client.get_as::<u16>("port").await,
+        Err(Error::Decode(_))
+    ));
+    assert!(matches!(
+        client.get_as::<u16>("big").await,
+        Err(Error::Decode(_))
+    ));
+}
+
+#[tokio::test]
+async fn json_documents() {
+    let client = start(&Storage::default());
+    let limits = Limits {
+        connections: 64,
+        hosts: vec!["a.example".into(), "b.example".into()],
+    };
+    client
+        .set("limits", Value::json(&limits).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(client.get_as::<Limits>("limits").await.unwrap(), limits);
+    let text = client.get("limits").await.unwrap();
+    assert!(text.starts_with("{\"connections\":64"));
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use magnetite::kv::ABSENT;
+use magnetite::{DeleteRequest, Error, Refusal, SetRequest};
+use magnetite_storage::Storage;
+
+use common::start;
+
+#[tokio::test]
+async fn every_write_increases_the_version() {
+    let storage = Storage::default();
+    let client = start(&storage);
+
+    let first = client.set("key", "a").await.unwrap();
+    let second = client.set("key", "b").await.unwrap();
+    client.delete("key").await.unwrap();
+    let third = client.set("key", "c").await.unwrap();
+
+    assert!(first < second && second < third);
+    let got = client.get_versioned("key").await.unwrap();
+    assert_eq!(got.value, "c");
+    assert_eq!(got.version, third);
+}
+
+#[tokio::test]
+async fn set_with_the_current_version_applies() {
+    let storage = Storage::default();
+    storage.insert("key", "old").unwrap();
+    let client = start(&storage);
+
+    let version = client.get_versioned("key").await.unwrap().version;
+    let request = SetRequest::new("key", "new").expected_version(version);
+
+    assert!(client.send_set(request).await.unwrap() > version);
+    assert_eq!(storage.get("key").as_deref(), Some("new"));
+}
+
+#[tokio::test]
+async fn set_with_a_stale_version_conflicts() {
+    let storage = Storage::default();
+    storage.insert("key", "old").unwrap();
+    let client = start(&storage);
+
+    let version = client.get_versioned("key").await.unwrap().version;
+    let current = client.set("key", "concurrent").await.unwrap();
+    let result = client
+        .send_set(SetRequest::new("key", "new").expected_version(version))
+        .await;
+
+    match result {
+        Err(Error::Refused(Refusal::Conflict {
+            key,
+            expected,
+            actual,
+        })) => {
+            assert_eq!(key, "key");
+            assert_eq!(expected, version);
+            assert_eq!(actual, current);
+        }
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    assert_eq!(storage.get("key").as_deref(), Some("concurrent"));
+}
+
+#[tokio::test]
+async fn absent_version_only_creates() {
+    let storage = Storage::default();
+    let client = start(&storage);
+
+    let create = SetRequest::new("key", "first").expected_version(ABSENT);
+    client.send_set(create.clone()).await.unwrap();
+    let result = client.send_set(create).await;
+
+    assert!(matches!(
+        result,
+        Err(Error::Refused(Refusal::Conflict { .. }))
+    ));
+    assert_eq!(storage.get("key").as_deref(), Some("first"));
+}
+
+#[tokio::test]
+async fn conditional_delete() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let version = client.set("key", "value").await.unwrap();
+
+    let stale = DeleteRequest::new("key").expected_version(version + 1);
+    assert!(matches!(
+        client.send_delete(stale).await,
+        Err(Error::Refused(Refusal::Conflict { .. }))
+    ));
+    assert_eq!(storage.get("key").as_deref(), Some("value"));
+
+    let current = DeleteRequest::new("key").expected_version(version);
+    assert!(client.send_delete(current).await.unwrap());
+    assert_eq!(storage.get("key"), None);
+}
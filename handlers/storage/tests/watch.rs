@@ -0,0 +1,130 @@
+mod common;
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use magnetite::request::RequestIds;
+use magnetite::{
+    Backend, BackendEvent, Client, Codec, Envelope, Event, Format, Message, MsgType, PeerId,
+    Service, Topics,
+};
+use magnetite_memory::MemoryNetwork;
+use magnetite_storage::Storage;
+use tokio::time::{sleep, timeout};
+
+use common::{serve, start};
+
+#[tokio::test]
+async fn watch_streams_changes_to_the_key() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let mut changes = client.watch("configservice.port").await.unwrap();
+
+    let created = client.set("configservice.port", "61250").await.unwrap();
+    let updated = client.set("configservice.port", "61251").await.unwrap();
+    client.delete("configservice.port").await.unwrap();
+
+    let change = changes.next().await.unwrap();
+    assert_eq!(change.key, "configservice.port");
+    assert_eq!(change.old, None);
+    assert_eq!(change.new.as_ref().map(|new| new.version), Some(created));
+
+    let change = changes.next().await.unwrap();
+    assert_eq!(change.old.unwrap().value, "61250");
+    let new = change.new.unwrap();
+    assert_eq!(new.value, "61251");
+    assert_eq!(new.version, updated);
+
+    let change = changes.next().await.unwrap();
+    assert_eq!(change.old.unwrap().version, updated);
+    assert_eq!(change.new, None);
+}
+
+#[tokio::test]
+async fn watch_prefix_skips_other_keys() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let mut changes = client.watch_prefix("configservice.").await.unwrap();
+
+    client.set("logservice.level", "debug").await.unwrap();
+    client.set("configservice.user", "public").await.unwrap();
+
+    let change = changes.next().await.unwrap();
+    assert_eq!(change.key, "configservice.user");
+}
+
+#[tokio::test]
+async fn refused_writes_are_not_notified() {
+    let storage = Storage::default();
+    let client = start(&storage);
+    let mut changes = client.watch("key").await.unwrap();
+
+    let stale = magnetite::SetRequest::new("key", "stale").expected_version(42);
+    assert!(client.send_set(stale).await.is_err());
+    client.set("key", "value").await.unwrap();
+
+    let change = changes.next().await.unwrap();
+    assert_eq!(change.new.unwrap().value, "value");
+}
+
+#[tokio::test]
+async fn only_the_watcher_ends_its_watch() {
+    let storage = Storage::default();
+    let network = serve(&storage);
+    let client = Client::builder().build(network.join("client"));
+    let mut mallory = network.join("mallory");
+    let topic = Topics::default().request();
+    mallory.subscribe(&topic).await.unwrap();
+    let mut changes = client.watch("configservice.port").await.unwrap();
+
+    // mallory overhears the watch
+    let overheard = async {
+        loop {
+            if let Some(BackendEvent::Message { data, .. }) = mallory.next_event().await {
+                let envelope = Envelope::decode(&Format::MessagePack, &data).unwrap();
+                if envelope.msgtype == MsgType::Watch {
+                    return envelope.correlation;
+                }
+            }
+        }
+    };
+    let watch = timeout(Duration::from_secs(5), overheard).await.unwrap();
+
+    // and ends it in the name of the client
+    let message = Message {
+        id: RequestIds::new(PeerId::new("client")).next_id(),
+        msgtype: MsgType::Unwatch,
+        payload: Format::MessagePack.encode(&watch).unwrap(),
+    };
+    let forged = Envelope::new(PeerId::new("client"), message)
+        .encode(&Format::MessagePack)
+        .unwrap();
+    mallory.publish(&topic, forged).await.unwrap();
+
+    client.set("configservice.port", "61250").await.unwrap();
+    let change = timeout(Duration::from_secs(5), changes.next())
+        .await
+        .unwrap();
+    assert_eq!(change.unwrap().key, "configservice.port");
+}
+
+#[tokio::test]
+async fn every_server_may_acknowledge_a_watch() {
+    let network = MemoryNetwork::new();
+    let (a, b) = (Storage::default(), Storage::default());
+    for (name, storage) in [("server-a", &a), ("server-b", &b)] {
+        Service::builder()
+            .handler(storage.clone())
+            .build(network.join(name))
+            .spawn();
+    }
+    let client = Client::builder().build(network.join("client"));
+    let mut events = client.events();
+    let _changes = client.watch("configservice.port").await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    // the second acknowledgement was dropped quietly
+    while let Ok(event) = events.try_recv() {
+        assert!(!matches!(event, Event::Error { .. }), "{:?}", event);
+    }
+}
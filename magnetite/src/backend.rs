@@ -0,0 +1,97 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Backend-agnostic identity of a peer on the mesh.
+///
+/// Backends pick their own textual representation (libp2p uses the base58
+/// encoded peer id), the only requirement is that it is stable and unique.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct PeerId(String);
+
+impl PeerId {
+    pub fn new(id: impl Into<String>) -> Self {
+        PeerId(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for PeerId {
+    fn from(id: String) -> Self {
+        PeerId(id)
+    }
+}
+
+impl From<&str> for PeerId {
+    fn from(id: &str) -> Self {
+        PeerId(id.to_owned())
+    }
+}
+
+/// Something that happened on the network side of a [`Backend`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackendEvent {
+    /// Raw message received on one of the subscribed topics.
+    Message {
+        source: PeerId,
+        topic: String,
+        data: Vec<u8>,
+    },
+    PeerConnected(PeerId),
+    PeerDisconnected(PeerId),
+    /// The backend started listening on the given address.
+    Listening(String),
+    /// A message [sent](Backend::send) to `peer` alone was not delivered.
+    Failed {
+        peer: PeerId,
+        error: String,
+    },
+}
+
+/// A peer-to-peer transport that magnetite services run on top of.
+///
+/// Implementations live in the `backends/*` crates. Everything above this
+/// trait (clients, handlers) only deals with topics and raw bytes, so the
+/// same service can be moved to another backend without changes.
+#[async_trait]
+pub trait Backend: Send + 'static {
+    /// Identity of this node on the mesh.
+    fn local_peer_id(&self) -> PeerId;
+
+    /// Number of peers messages can currently be published to.
+    fn peer_count(&self) -> usize;
+
+    async fn subscribe(&mut self, topic: &str) -> Result<()>;
+
+    /// Sends `data` to the peers subscribed to `topic`, failing with
+    /// [`Error::InsufficientPeers`](crate::Error::InsufficientPeers) if there are none.
+    async fn publish(&mut self, topic: &str, data: Vec<u8>) -> Result<()>;
+
+    /// Sends `data` to `peer` alone, received as a message on `topic`.
+    ///
+    /// Backends without a point-to-point channel publish it on `topic`, so
+    /// it should be a topic only `peer` subscribes to, like its
+    /// [direct topic](crate::Topics::direct).
+    async fn send(&mut self, peer: &PeerId, topic: &str, data: Vec<u8>) -> Result<()> {
+        let _ = peer;
+        self.publish(topic, data).await
+    }
+
+    /// Waits for the next event, `None` means the backend has shut down.
+    ///
+    /// This is used inside `select!` loops, so it must be cancel-safe: an
+    /// event must not be lost if the returned future is dropped early.
+    async fn next_event(&mut self) -> Option<BackendEvent>;
+}
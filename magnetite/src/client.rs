@@ -0,0 +1,954 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream;
+use serde::de::DeserializeOwned;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::request::{PendingRequests, RequestId, RequestIds};
+use crate::{
+    Announcement, Backend, BackendEvent, ChangeEvent, Codec, DeleteRequest, Dump, Envelope, Error,
+    Event, Format, Forward, Instance, LeaseId, ListPage, ListRequest, Message, MsgType, PeerId,
+    Refusal, Result, RetryPolicy, Route, SetRequest, Topics, Value, Versioned, WatchRequest,
+};
+
+pub struct ClientBuilder {
+    wanted: Vec<String>,
+    defaults: HashMap<String, String>,
+    topics: Topics,
+    retry: RetryPolicy,
+    min_peers: usize,
+    keyspace: Option<String>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            wanted: Vec::new(),
+            defaults: HashMap::new(),
+            topics: Topics::default(),
+            retry: RetryPolicy::default(),
+            min_peers: 1,
+            keyspace: None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Key that is fetched as soon as the client starts, see [`Client::ready`].
+    ///
+    /// While the key does not exist the request keeps being retried, until
+    /// it appears or the [`RetryPolicy`] gives up.
+    pub fn want(mut self, key: impl Into<String>) -> Self {
+        self.wanted.push(key.into());
+        self
+    }
+
+    /// Like [`ClientBuilder::want`], but resolves to `default` if the key does
+    /// not exist.
+    pub fn want_or(mut self, key: impl Into<String>, default: impl Into<String>) -> Self {
+        let key = key.into();
+        self.defaults.insert(key.clone(), default.into());
+        self.want(key)
+    }
+
+    pub fn wants<I, K>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        self.wanted.extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Topics of the service the client talks to.
+    pub fn topics(mut self, topics: Topics) -> Self {
+        self.topics = topics;
+        self
+    }
+
+    /// Deadline and republishing schedule of every request, wanted keys included.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Requests are held back until the backend sees at least this many peers.
+    pub fn min_peers(mut self, peers: usize) -> Self {
+        self.min_peers = peers;
+        self
+    }
+
+    /// Keyspace of the client's requests, wanted keys included, the
+    /// default keyspace otherwise. See [`Client::in_keyspace`].
+    pub fn keyspace(mut self, keyspace: impl Into<String>) -> Self {
+        self.keyspace = Some(keyspace.into());
+        self
+    }
+
+    /// Starts the client on `backend`, must be called from within a tokio runtime.
+    pub fn build<B: Backend>(self, backend: B) -> Client {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let wanted = self
+            .wanted
+            .iter()
+            .map(|key| (key.clone(), Wanted::Pending))
+            .collect();
+        let (wanted_tx, wanted_rx) = watch::channel(wanted);
+        let (events, _) = broadcast::channel(64);
+
+        let local_peer_id = backend.local_peer_id();
+        let format = self.topics.codec();
+        let mut driver = Driver {
+            backend,
+            commands: receiver,
+            wanted: wanted_tx,
+            pending: PendingRequests::new(local_peer_id.clone()),
+            defaults: self.defaults,
+            watches: HashMap::new(),
+            unwatched: Vec::new(),
+            ids: RequestIds::new(local_peer_id),
+            topics: self.topics,
+            retry: self.retry,
+            min_peers: self.min_peers,
+            events: events.clone(),
+        };
+        for key in self.wanted {
+            match format.encode(&key) {
+                Ok(payload) => {
+                    let target = Target {
+                        keyspace: self.keyspace.clone(),
+                        path: Path::Mesh,
+                    };
+                    driver.register(MsgType::Get, key, target, payload, None);
+                }
+                Err(error) => driver.wanted.send_modify(|wanted| {
+                    wanted.insert(key, Wanted::Failed(error));
+                }),
+            }
+        }
+        tokio::spawn(driver.run());
+
+        Client {
+            commands,
+            format,
+            target: Target {
+                keyspace: self.keyspace,
+                path: Path::Mesh,
+            },
+            wanted: wanted_rx,
+            events,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Wanted {
+    Pending,
+    Resolved(String),
+    Failed(Error),
+}
+
+/// Handle to a running client, cheap to clone.
+///
+/// Dropping the last handle stops the task driving the backend, dropping a
+/// request's future stops that request from being published again.
+#[derive(Clone)]
+pub struct Client {
+    commands: mpsc::UnboundedSender<Command>,
+    /// Format requests are encoded with, the one of the service's topics.
+    format: Format,
+    target: Target,
+    wanted: watch::Receiver<HashMap<String, Wanted>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Client {
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Handle whose requests go to `keyspace`, sharing this client's
+    /// connection. Wanted keys stay in the keyspace of the builder.
+    pub fn in_keyspace(&self, keyspace: impl Into<String>) -> Client {
+        let mut client = self.clone();
+        client.target.keyspace = Some(keyspace.into());
+        client
+    }
+
+    /// Handle whose requests only reach `route.to`, forwarded by a relay,
+    /// sharing this client's connection. Wanted keys are not routed.
+    ///
+    /// The routed peer replies as it would to a request it saw itself.
+    pub fn routed(&self, route: Route) -> Client {
+        let mut client = self.clone();
+        client.target.path = Path::Routed(route);
+        client
+    }
+
+    /// Handle whose requests go straight to `peer` alone, over the
+    /// backend's point-to-point channel, sharing this client's connection.
+    /// Wanted keys are not sent directly.
+    ///
+    /// `peer` replies the same way, backends without such a channel fall
+    /// back to topics only the two of them subscribe to, see
+    /// [`Backend::send`].
+    pub fn direct(&self, peer: PeerId) -> Client {
+        let mut client = self.clone();
+        client.target.path = Path::Direct(peer);
+        client
+    }
+
+    /// Subscribes to the client's events, only events sent after this call
+    /// are received.
+    pub fn events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Current values of the wanted keys, `None` for the ones not resolved yet.
+    pub fn wanted(&self) -> HashMap<String, Option<String>> {
+        self.wanted
+            .borrow()
+            .iter()
+            .map(|(key, wanted)| {
+                let value = match wanted {
+                    Wanted::Resolved(value) => Some(value.clone()),
+                    _ => None,
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+
+    /// Resolves once every wanted key has received a value, or fails as soon
+    /// as one of them can't be resolved.
+    pub async fn ready(&self) -> Result<HashMap<String, String>> {
+        let mut wanted = self.wanted.clone();
+        let done = wanted
+            .wait_for(|values| {
+                values
+                    .values()
+                    .all(|wanted| !matches!(wanted, Wanted::Pending))
+                    || values
+                        .values()
+                        .any(|wanted| matches!(wanted, Wanted::Failed(_)))
+            })
+            .await
+            .map_err(|_| Error::Closed)?;
+
+        let mut resolved = HashMap::new();
+        for (key, wanted) in done.iter() {
+            match wanted {
+                Wanted::Resolved(value) => {
+                    resolved.insert(key.clone(), value.clone());
+                }
+                Wanted::Failed(err) => return Err(err.clone()),
+                Wanted::Pending => {}
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Asks the mesh for the value of `key` as text, fails with a
+    /// [`Refusal::NotFound`] if there is none, see [`Error::is_not_found`].
+    ///
+    /// Ints and bools are written out, byte values fail with
+    /// [`Error::Decode`], see [`Client::get_as`] for those.
+    pub async fn get(&self, key: &str) -> Result<String> {
+        self.get_value(key).await?.into_text(key)
+    }
+
+    /// Asks the mesh for the value of `key`, decoded into `T`, see
+    /// [`Value::decode`].
+    pub async fn get_as<T: DeserializeOwned>(&self, key: &str) -> Result<T> {
+        self.get_value(key).await?.decode()
+    }
+
+    pub async fn get_value(&self, key: &str) -> Result<Value> {
+        Ok(self.get_versioned(key).await?.value)
+    }
+
+    /// Asks the mesh for the value of `key`, `default` if there is none.
+    pub async fn get_or(&self, key: &str, default: &str) -> Result<String> {
+        match self.get(key).await {
+            Err(err) if err.is_not_found() => Ok(default.to_owned()),
+            got => got,
+        }
+    }
+
+    /// Asks the mesh for the value of `key`, and the version it is at.
+    pub async fn get_versioned(&self, key: &str) -> Result<Versioned> {
+        let payload = self.format.encode(key)?;
+        self.call(MsgType::Get, key, payload).await
+    }
+
+    /// Stores `value` under `key`, resolves with the version it was written at.
+    pub async fn set(&self, key: &str, value: impl Into<Value>) -> Result<u64> {
+        self.send_set(SetRequest::new(key, value)).await
+    }
+
+    /// Sends a `Set`, fails with [`Error::Refused`] if the server did not apply
+    /// it, with a [`Refusal::Conflict`] if the expected version did not match.
+    pub async fn send_set(&self, request: SetRequest) -> Result<u64> {
+        let payload = self.format.encode(&request)?;
+        self.call(MsgType::Set, &request.key, payload).await
+    }
+
+    /// Removes `key`, resolves with whether it existed.
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        self.send_delete(DeleteRequest::new(key)).await
+    }
+
+    /// Sends a `Delete`, fails like [`Client::send_set`].
+    pub async fn send_delete(&self, request: DeleteRequest) -> Result<bool> {
+        let payload = self.format.encode(&request)?;
+        self.call(MsgType::Delete, &request.key, payload).await
+    }
+
+    /// Whether the mesh holds a value for `key`.
+    pub async fn exists(&self, key: &str) -> Result<bool> {
+        let payload = self.format.encode(key)?;
+        self.call(MsgType::Exists, key, payload).await
+    }
+
+    /// Fetches one page of keys, pass the page's `next` as the cursor of the
+    /// following request to continue.
+    pub async fn list(&self, request: ListRequest) -> Result<ListPage> {
+        let payload = self.format.encode(&request)?;
+        self.call(MsgType::List, &request.prefix, payload).await
+    }
+
+    /// Every key starting with `prefix`, fetched page by page.
+    pub async fn list_all(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut request = ListRequest::new(prefix);
+        loop {
+            let page = self.list(request.clone()).await?;
+            keys.extend(page.keys);
+            match page.next {
+                Some(cursor) => request = request.cursor(cursor),
+                None => return Ok(keys),
+            }
+        }
+    }
+
+    /// Every entry of the keyspace, as of a single revision.
+    pub async fn dump(&self) -> Result<Dump> {
+        let payload = self.format.encode(&())?;
+        self.call(MsgType::Dump, self.keyspace_key(), payload).await
+    }
+
+    /// Removes every key of the keyspace at once, resolves with how many
+    /// there were. The default keyspace can't be wiped.
+    pub async fn wipe(&self) -> Result<u64> {
+        let payload = self.format.encode(&())?;
+        self.call(MsgType::Wipe, self.keyspace_key(), payload).await
+    }
+
+    /// Creates a lease living for `ttl`, keys set with it are deleted once
+    /// it is neither kept alive nor revoked in time.
+    pub async fn grant(&self, ttl: Duration) -> Result<LeaseId> {
+        let payload = self.format.encode(&ttl)?;
+        self.call(MsgType::Grant, "lease", payload).await
+    }
+
+    /// Renews `lease` for its whole time to live, fails with a
+    /// [`Refusal::LeaseExpired`] if it already ended.
+    pub async fn keep_alive(&self, lease: &LeaseId) -> Result<()> {
+        let payload = self.format.encode(lease)?;
+        self.call(MsgType::KeepAlive, &lease.to_string(), payload)
+            .await
+    }
+
+    /// Ends `lease` now, resolves with how many keys it deleted.
+    pub async fn revoke(&self, lease: &LeaseId) -> Result<u64> {
+        let payload = self.format.encode(lease)?;
+        self.call(MsgType::Revoke, &lease.to_string(), payload)
+            .await
+    }
+
+    /// Stands for the keyspace in errors, where a key would otherwise be.
+    fn keyspace_key(&self) -> &str {
+        self.target.keyspace.as_deref().unwrap_or_default()
+    }
+
+    /// Streams every change made to `key` from now on.
+    pub async fn watch(&self, key: &str) -> Result<Watch> {
+        self.send_watch(WatchRequest::key(key)).await
+    }
+
+    /// Streams every change made to keys starting with `prefix` from now on.
+    pub async fn watch_prefix(&self, prefix: &str) -> Result<Watch> {
+        self.send_watch(WatchRequest::prefix(prefix)).await
+    }
+
+    /// Resolves once the server acknowledged the watch, dropping the
+    /// returned [`Watch`] ends it.
+    pub async fn send_watch(&self, request: WatchRequest) -> Result<Watch> {
+        let payload = self.format.encode(&request)?;
+        let (changes, receiver) = mpsc::unbounded_channel();
+        let (format, reply) = self
+            .request(
+                MsgType::Watch,
+                &request.key,
+                payload,
+                Some(Changes::Keys(changes)),
+            )
+            .await?;
+        let reply: std::result::Result<(), Refusal> = format.decode(&reply)?;
+        reply.map_err(Error::Refused)?;
+        Ok(Watch { changes: receiver })
+    }
+
+    /// Lists an instance of a service, announce again before the
+    /// announcement's time to live runs out to stay listed.
+    pub async fn announce(&self, announcement: &Announcement) -> Result<()> {
+        let payload = self.format.encode(announcement)?;
+        self.call(MsgType::Announce, &announcement.name, payload)
+            .await
+    }
+
+    /// Takes this client's instance of `service` off the list, resolves with
+    /// whether it was listed.
+    pub async fn withdraw(&self, service: &str) -> Result<bool> {
+        let payload = self.format.encode(service)?;
+        self.call(MsgType::Withdraw, service, payload).await
+    }
+
+    /// The live instances of `service`, none if it is unknown.
+    pub async fn resolve(&self, service: &str) -> Result<Vec<Instance>> {
+        let payload = self.format.encode(service)?;
+        self.call(MsgType::Resolve, service, payload).await
+    }
+
+    /// Streams the live instances of `service`, first as they are now and
+    /// again every time they change. Resolves once the server acknowledged
+    /// the subscription, dropping the returned [`Instances`] ends it.
+    pub async fn subscribe(&self, service: &str) -> Result<Instances> {
+        let payload = self.format.encode(service)?;
+        let (updates, receiver) = mpsc::unbounded_channel();
+        let (format, reply) = self
+            .request(
+                MsgType::Subscribe,
+                service,
+                payload,
+                Some(Changes::Instances(updates)),
+            )
+            .await?;
+        let reply: std::result::Result<(), Refusal> = format.decode(&reply)?;
+        reply.map_err(Error::Refused)?;
+        Ok(Instances {
+            updates: receiver,
+            last: None,
+        })
+    }
+
+    /// Gives up on every outstanding request, wanted keys included, they
+    /// fail with [`Error::Cancelled`].
+    pub fn cancel(&self) {
+        let _ = self.commands.send(Command::Cancel);
+    }
+
+    /// Sends a storage request, and decodes its `Result<T, Refusal>` reply.
+    async fn call<T: DeserializeOwned>(
+        &self,
+        msgtype: MsgType,
+        key: &str,
+        payload: Vec<u8>,
+    ) -> Result<T> {
+        let (format, reply) = self.request(msgtype, key, payload, None).await?;
+        let reply: std::result::Result<T, Refusal> = format.decode(&reply)?;
+        reply.map_err(Error::Refused)
+    }
+
+    /// Resolves with the reply's payload and the format it is encoded with.
+    ///
+    /// Notifications sent after the reply with the request's id go to `changes`.
+    async fn request(
+        &self,
+        msgtype: MsgType,
+        key: &str,
+        payload: Vec<u8>,
+        changes: Option<Changes>,
+    ) -> Result<(Format, Vec<u8>)> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(Command::Request {
+                msgtype,
+                key: key.to_owned(),
+                target: self.target.clone(),
+                payload,
+                reply,
+                changes,
+            })
+            .map_err(|_| Error::Closed)?;
+        response.await.map_err(|_| Error::Closed)?
+    }
+}
+
+/// Changes pushed to a watched key or prefix, see [`Client::watch`].
+///
+/// The stream ends when the client stops.
+pub struct Watch {
+    changes: mpsc::UnboundedReceiver<ChangeEvent>,
+}
+
+impl Stream for Watch {
+    type Item = ChangeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ChangeEvent>> {
+        self.changes.poll_recv(cx)
+    }
+}
+
+/// Live instances of a service, see [`Client::subscribe`].
+///
+/// Lists equal to the previous one, as sent by every server of a replicated
+/// registry, are skipped. The stream ends when the client stops.
+pub struct Instances {
+    updates: mpsc::UnboundedReceiver<Vec<Instance>>,
+    last: Option<Vec<Instance>>,
+}
+
+impl Stream for Instances {
+    type Item = Vec<Instance>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<Instance>>> {
+        loop {
+            match self.updates.poll_recv(cx) {
+                Poll::Ready(Some(instances)) if self.last.as_ref() == Some(&instances) => {}
+                Poll::Ready(Some(instances)) => {
+                    self.last = Some(instances.clone());
+                    return Poll::Ready(Some(instances));
+                }
+                other => return other,
+            }
+        }
+    }
+}
+
+type Reply = oneshot::Sender<Result<(Format, Vec<u8>)>>;
+
+/// Where the notifications pushed for a request go, by what they hold.
+enum Changes {
+    Keys(mpsc::UnboundedSender<ChangeEvent>),
+    Instances(mpsc::UnboundedSender<Vec<Instance>>),
+}
+
+impl Changes {
+    fn is_closed(&self) -> bool {
+        match self {
+            Changes::Keys(changes) => changes.is_closed(),
+            Changes::Instances(updates) => updates.is_closed(),
+        }
+    }
+
+    /// Decodes and hands over a notification, returns whether anyone is
+    /// still listening.
+    fn deliver(&self, format: Format, payload: &[u8]) -> Result<bool> {
+        Ok(match self {
+            Changes::Keys(changes) => changes.send(format.decode(payload)?).is_ok(),
+            Changes::Instances(updates) => updates.send(format.decode(payload)?).is_ok(),
+        })
+    }
+}
+
+/// Where a client's requests go.
+#[derive(Clone, Debug)]
+struct Target {
+    keyspace: Option<String>,
+    path: Path,
+}
+
+#[derive(Clone, Debug)]
+enum Path {
+    /// Published to every server of the service.
+    Mesh,
+    /// Forwarded by a relay.
+    Routed(Route),
+    Direct(PeerId),
+}
+
+enum Command {
+    Request {
+        msgtype: MsgType,
+        key: String,
+        target: Target,
+        payload: Vec<u8>,
+        reply: Reply,
+        changes: Option<Changes>,
+    },
+    Cancel,
+}
+
+/// Longest wait between two publishes of a request, whatever the policy.
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct Pending {
+    key: String,
+    target: Target,
+    request: Message,
+    /// `None` for wanted keys, their value goes into the wanted map instead.
+    reply: Option<Reply>,
+    attempts: u32,
+    next_attempt: Instant,
+    deadline: Option<Instant>,
+}
+
+struct Driver<B> {
+    backend: B,
+    commands: mpsc::UnboundedReceiver<Command>,
+    wanted: watch::Sender<HashMap<String, Wanted>>,
+    pending: PendingRequests<Pending>,
+    /// Values of the wanted keys that may not exist.
+    defaults: HashMap<String, String>,
+    /// Acknowledged watches and subscriptions, by the id of their request.
+    watches: HashMap<RequestId, Changes>,
+    /// Watches the application dropped, the server is told on the next occasion.
+    unwatched: Vec<RequestId>,
+    ids: RequestIds,
+    topics: Topics,
+    retry: RetryPolicy,
+    min_peers: usize,
+    events: broadcast::Sender<Event>,
+}
+
+impl<B: Backend> Driver<B> {
+    async fn run(mut self) {
+        let reply_topic = self.topics.reply(&self.backend.local_peer_id());
+        if let Err(err) = self.backend.subscribe(&reply_topic).await {
+            warn!("failed to subscribe to {}: {}", reply_topic, err);
+            return;
+        }
+
+        loop {
+            let wakeup = self.next_wakeup();
+            tokio::select! {
+                _ = sleep_until(wakeup) => self.ask().await,
+                command = self.commands.recv() => match command {
+                    Some(Command::Request { msgtype, key, target, payload, reply, changes }) => {
+                        let id = self.register(msgtype, key, target, payload, Some(reply));
+                        if let Some(changes) = changes {
+                            self.watches.insert(id, changes);
+                        }
+                        self.ask().await;
+                    }
+                    Some(Command::Cancel) => {
+                        for id in self.pending.ids() {
+                            self.fail(&id, Error::Cancelled);
+                        }
+                    }
+                    // every client handle is gone
+                    None => break,
+                },
+                event = self.backend.next_event() => match event {
+                    Some(BackendEvent::Message { source, data, .. }) => {
+                        if let Err(error) = self.process(&data) {
+                            debug!("dropped message from {}: {}", source, error);
+                            self.emit(Event::Error { source: Some(source), error });
+                        }
+                        self.unwatch().await;
+                    }
+                    Some(BackendEvent::PeerConnected(peer)) => self.emit(Event::PeerConnected(peer)),
+                    Some(BackendEvent::PeerDisconnected(peer)) => {
+                        self.emit(Event::PeerDisconnected(peer))
+                    }
+                    Some(BackendEvent::Listening(addr)) => self.emit(Event::Listening(addr)),
+                    Some(BackendEvent::Failed { peer, error }) => self.emit(Event::Error {
+                        source: Some(peer),
+                        error: Error::backend(error),
+                    }),
+                    None => break,
+                },
+            }
+        }
+    }
+
+    fn register(
+        &mut self,
+        msgtype: MsgType,
+        key: String,
+        target: Target,
+        payload: Vec<u8>,
+        reply: Option<Reply>,
+    ) -> RequestId {
+        let id = self.ids.next_id();
+        let now = Instant::now();
+        let request = Message {
+            id: id.clone(),
+            msgtype,
+            payload,
+        };
+        self.pending.insert(
+            id.clone(),
+            Pending {
+                key,
+                target,
+                request,
+                reply,
+                attempts: 0,
+                next_attempt: now,
+                // a deadline too far to tell is none
+                deadline: self
+                    .retry
+                    .timeout
+                    .and_then(|timeout| now.checked_add(timeout)),
+            },
+        );
+        id
+    }
+
+    fn next_wakeup(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|pending| match pending.deadline {
+                Some(deadline) => pending.next_attempt.min(deadline),
+                None => pending.next_attempt,
+            })
+            .min()
+    }
+
+    /// Publishes the requests that are due, and fails the ones out of time.
+    async fn ask(&mut self) {
+        // requests whose future was dropped
+        self.pending
+            .retain(|_, pending| !matches!(&pending.reply, Some(reply) if reply.is_closed()));
+        let pending = &self.pending;
+        let unwatched = &mut self.unwatched;
+        self.watches.retain(|id, changes| {
+            let dropped = changes.is_closed() && pending.get(id).is_none();
+            if dropped {
+                unwatched.push(id.clone());
+            }
+            !dropped
+        });
+        self.unwatch().await;
+
+        let now = Instant::now();
+        for id in self.pending.ids() {
+            let (key, attempts, due, expired) = match self.pending.get(&id) {
+                Some(pending) => {
+                    let exhausted = self
+                        .retry
+                        .max_attempts
+                        .is_some_and(|max| pending.attempts >= max);
+                    let due = pending.next_attempt <= now;
+                    let expired = pending.deadline.is_some_and(|deadline| deadline <= now)
+                        || (exhausted && due);
+                    (pending.key.clone(), pending.attempts, due, expired)
+                }
+                None => continue,
+            };
+            if expired {
+                debug!("giving up on request {} for {}", id, key);
+                self.fail(&id, Error::Timeout { key, attempts });
+            } else if due {
+                let published = self.publish(&id).await;
+                if let Some(pending) = self.pending.get_mut(&id) {
+                    if published {
+                        pending.attempts += 1;
+                    }
+                    let backoff = self.retry.backoff(pending.attempts.max(1)).min(MAX_BACKOFF);
+                    pending.next_attempt = now + backoff;
+                }
+            }
+        }
+    }
+
+    async fn publish(&mut self, id: &RequestId) -> bool {
+        if self.backend.peer_count() < self.min_peers {
+            return false;
+        }
+        let local = self.ids.peer();
+        let format = self.topics.codec();
+        let (envelope, path) = match self.pending.get(id) {
+            Some(pending) => (
+                Envelope::new(local.clone(), pending.request.clone())
+                    .reply_to(self.topics.reply(local))
+                    .content_type(format.content_type())
+                    .keyspace(pending.target.keyspace.clone()),
+                pending.target.path.clone(),
+            ),
+            None => return false,
+        };
+        let published = match path {
+            Path::Mesh => match envelope.encode(&format) {
+                Ok(data) => self.backend.publish(&self.topics.request(), data).await,
+                Err(error) => Err(error),
+            },
+            Path::Routed(route) => {
+                // relays answer refusals with the id of the request they carry
+                let forward = format
+                    .encode(&Forward { route, envelope })
+                    .and_then(|payload| {
+                        let message = Message {
+                            id: id.clone(),
+                            msgtype: MsgType::Forward,
+                            payload,
+                        };
+                        Envelope::new(local.clone(), message)
+                            .reply_to(self.topics.reply(local))
+                            .content_type(format.content_type())
+                            .encode(&format)
+                    });
+                match forward {
+                    Ok(data) => self.backend.publish(&self.topics.request(), data).await,
+                    Err(error) => Err(error),
+                }
+            }
+            Path::Direct(peer) => match envelope.encode(&format) {
+                Ok(data) => {
+                    let topic = self.topics.direct(&peer);
+                    self.backend.send(&peer, &topic, data).await
+                }
+                Err(error) => Err(error),
+            },
+        };
+        match published {
+            Ok(()) => true,
+            // expected while the mesh is forming, the request is retried
+            Err(Error::InsufficientPeers) => false,
+            Err(error) => {
+                debug!("failed to publish request {}: {}", id, error);
+                self.emit(Event::Error {
+                    source: None,
+                    error,
+                });
+                false
+            }
+        }
+    }
+
+    /// Tells the server about dropped watches, a lost `Unwatch` only costs
+    /// the server some notifications, so it is sent once.
+    async fn unwatch(&mut self) {
+        let format = self.topics.codec();
+        for watch in std::mem::take(&mut self.unwatched) {
+            let message = Message {
+                id: self.ids.next_id(),
+                msgtype: MsgType::Unwatch,
+                payload: match format.encode(&watch) {
+                    Ok(payload) => payload,
+                    Err(_) => continue,
+                },
+            };
+            let envelope =
+                Envelope::new(self.ids.peer().clone(), message).content_type(format.content_type());
+            let published = match envelope.encode(&format) {
+                Ok(data) => self.backend.publish(&self.topics.request(), data).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = published {
+                debug!("failed to unwatch {}: {}", watch, error);
+            }
+        }
+    }
+
+    fn fail(&mut self, id: &RequestId, err: Error) {
+        self.watches.remove(id);
+        let pending = match self.pending.resolve(id) {
+            Some(pending) => pending,
+            None => return,
+        };
+        match pending.reply {
+            Some(reply) => {
+                let _ = reply.send(Err(err));
+            }
+            None => self.wanted.send_modify(|wanted| {
+                wanted.insert(pending.key, Wanted::Failed(err));
+            }),
+        }
+    }
+
+    fn process(&mut self, what: &[u8]) -> Result<()> {
+        let envelope = Envelope::decode(&self.topics.codec(), what)?;
+        let format =
+            Format::from_content_type(&envelope.content_type).unwrap_or(self.topics.codec());
+        let message_raw = envelope.into_message();
+
+        // requests are for the servers, unknown types come from newer peers
+        if message_raw.msgtype != MsgType::Notification {
+            return Ok(());
+        }
+        let pending = match self.pending.resolve(&message_raw.id) {
+            Some(pending) => pending,
+            None if self.watches.contains_key(&message_raw.id) => {
+                return self.changed(message_raw.id, format, &message_raw.payload);
+            }
+            // replies to other peers' requests end up here as well
+            None if &message_raw.id.peer != self.ids.peer() => return Ok(()),
+            None => return Err(Error::UnknownCorrelation(message_raw.id)),
+        };
+        match pending.reply {
+            Some(reply) => {
+                let _ = reply.send(Ok((format, message_raw.payload)));
+            }
+            None => {
+                let value = match format
+                    .decode::<std::result::Result<Versioned, Refusal>>(&message_raw.payload)
+                {
+                    Ok(Ok(got)) => got.value.into_text(&pending.key),
+                    Ok(Err(Refusal::NotFound { .. })) => {
+                        match self.defaults.get(&pending.key) {
+                            Some(default) => Ok(default.clone()),
+                            None => {
+                                // asked again on the retry schedule, until it shows up
+                                self.pending.insert(pending.request.id.clone(), pending);
+                                return Ok(());
+                            }
+                        }
+                    }
+                    Ok(Err(refusal)) => Err(Error::Refused(refusal)),
+                    Err(error) => Err(error),
+                };
+                let result = value.clone().map(|_| ());
+                self.wanted.send_modify(|wanted| {
+                    let value = match value {
+                        Ok(value) => Wanted::Resolved(value),
+                        Err(error) => Wanted::Failed(error),
+                    };
+                    wanted.insert(pending.key, value);
+                });
+                return result;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands a change pushed by the server to the watch it belongs to.
+    fn changed(&mut self, watch: RequestId, format: Format, payload: &[u8]) -> Result<()> {
+        // every server answering the request acknowledges it, the first
+        // acknowledgement was its reply
+        if format
+            .decode::<std::result::Result<(), Refusal>>(payload)
+            .is_ok()
+        {
+            return Ok(());
+        }
+        let delivered = match self.watches.get(&watch) {
+            Some(changes) => changes.deliver(format, payload)?,
+            None => return Ok(()),
+        };
+        if !delivered {
+            self.watches.remove(&watch);
+            self.unwatched.push(watch);
+        }
+        Ok(())
+    }
+
+    fn emit(&self, event: Event) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
@@ -0,0 +1,178 @@
+//! Serialization of envelopes and payloads.
+//!
+//! MessagePack is always available, the other formats are behind the `cbor`,
+//! `json` and `bincode` cargo features. The format of the envelopes on a
+//! topic is declared with [`Topics::format`](crate::Topics::format), the
+//! format of a payload travels in its envelope's `content_type`, and replies
+//! are encoded in the format of the request.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::Result;
+
+/// A serialization format, in the spirit of the serde data formats.
+pub trait Codec {
+    /// MIME type written into the envelopes of payloads using this codec.
+    fn content_type(&self) -> &'static str;
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>>;
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T>;
+}
+
+/// Compact binary format, the default.
+///
+/// Structs are written as maps keyed by field name, so fields can be added
+/// without breaking older peers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    fn content_type(&self) -> &'static str {
+        "application/msgpack"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        Ok(rmp_serde::from_slice(data)?)
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn content_type(&self) -> &'static str {
+        "application/cbor"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(value, &mut buf)
+            .map_err(|err| crate::Error::Encode(err.to_string()))?;
+        Ok(buf)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        ciborium::de::from_reader(data).map_err(|err| crate::Error::Decode(err.to_string()))
+    }
+}
+
+/// Human readable, meant for debugging tools rather than production traffic.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|err| crate::Error::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).map_err(|err| crate::Error::Decode(err.to_string()))
+    }
+}
+
+/// The most compact format, but not self-describing: every peer on a
+/// bincode topic must run the same protocol version.
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn content_type(&self) -> &'static str {
+        "application/bincode"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|err| crate::Error::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        bincode::deserialize(data).map_err(|err| crate::Error::Decode(err.to_string()))
+    }
+}
+
+/// One of the built-in codecs, picked at runtime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Format {
+    #[default]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl Format {
+    /// Every format compiled into this build.
+    pub const ALL: &'static [Format] = &[
+        Format::MessagePack,
+        #[cfg(feature = "cbor")]
+        Format::Cbor,
+        #[cfg(feature = "json")]
+        Format::Json,
+        #[cfg(feature = "bincode")]
+        Format::Bincode,
+    ];
+
+    /// The format an envelope's `content_type` refers to, if it is compiled in.
+    pub fn from_content_type(content_type: &str) -> Option<Format> {
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|format| format.content_type() == content_type)
+    }
+}
+
+impl Codec for Format {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Format::MessagePack => MessagePack.content_type(),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => Cbor.content_type(),
+            #[cfg(feature = "json")]
+            Format::Json => Json.content_type(),
+            #[cfg(feature = "bincode")]
+            Format::Bincode => Bincode.content_type(),
+        }
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Format::MessagePack => MessagePack.encode(value),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => Cbor.encode(value),
+            #[cfg(feature = "json")]
+            Format::Json => Json.encode(value),
+            #[cfg(feature = "bincode")]
+            Format::Bincode => Bincode.encode(value),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        match self {
+            Format::MessagePack => MessagePack.decode(data),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => Cbor.decode(data),
+            #[cfg(feature = "json")]
+            Format::Json => Json.decode(data),
+            #[cfg(feature = "bincode")]
+            Format::Bincode => Bincode.decode(data),
+        }
+    }
+}
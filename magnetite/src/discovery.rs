@@ -0,0 +1,93 @@
+//! Bodies of the service discovery requests, answered by registry handlers.
+//!
+//! Every instance of a service `Announce`s itself, and announces again
+//! before the announcement's time to live runs out to stay listed, or
+//! `Withdraw`s when shutting down. `Resolve` replies with the live instances
+//! of a service. `Subscribe` is acknowledged, then pushes them as a
+//! notification, and again every time they change, until the subscriber
+//! sends `Unwatch` with the id of its `Subscribe` request.
+//!
+//! Replies are encoded as a `Result<T, Refusal>`, like the key/value ones.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::PeerId;
+
+/// Time to live of an [`Announcement`] unless told otherwise.
+pub const DEFAULT_ANNOUNCE_TTL: Duration = Duration::from_secs(30);
+
+/// How an instance reports doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Health {
+    Passing,
+    /// Working, but worth avoiding when another instance passes.
+    Warning,
+    /// Listed, but not serving.
+    Critical,
+}
+
+/// Body of an `Announce` request, one per service an instance provides.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Announcement {
+    pub name: String,
+    /// Where the instance can be reached, in whatever form its clients use.
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    pub health: Health,
+    /// The instance is dropped once this long passed without another
+    /// announcement.
+    pub ttl: Duration,
+}
+
+impl Announcement {
+    pub fn new(name: impl Into<String>) -> Self {
+        Announcement {
+            name: name.into(),
+            addresses: Vec::new(),
+            metadata: BTreeMap::new(),
+            health: Health::Passing,
+            ttl: DEFAULT_ANNOUNCE_TTL,
+        }
+    }
+
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.addresses.push(address.into());
+        self
+    }
+
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+}
+
+/// A live instance of a service, as announced by `peer`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Instance {
+    pub peer: PeerId,
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    pub health: Health,
+}
+
+impl Instance {
+    /// Whether the instance says it serves.
+    pub fn is_serving(&self) -> bool {
+        self.health != Health::Critical
+    }
+}
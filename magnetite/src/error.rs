@@ -0,0 +1,88 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{Refusal, RequestId};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong between a backend and a client or handler.
+///
+/// Errors are cheap to clone, so the same error can be handed to a request's
+/// caller and reported as an [`Event`](crate::Event).
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// A received message is not a valid [`Envelope`](crate::Envelope).
+    Decode(String),
+    /// The envelope uses a protocol version this build no longer supports.
+    UnsupportedVersion(u16),
+    /// A message could not be serialized.
+    Encode(String),
+    /// There is no peer to publish the message to.
+    InsufficientPeers,
+    /// A reply addressed to this peer does not belong to any pending request,
+    /// usually because the request already timed out.
+    UnknownCorrelation(RequestId),
+    /// Any other failure reported by the backend.
+    Backend(Arc<dyn std::error::Error + Send + Sync>),
+    /// The task driving the backend has stopped.
+    Closed,
+    /// No reply arrived within the [`RetryPolicy`](crate::RetryPolicy) limits.
+    Timeout { key: String, attempts: u32 },
+    /// The request was given up with [`Client::cancel`](crate::Client::cancel).
+    Cancelled,
+    /// The server answered, but did not apply the request.
+    Refused(Refusal),
+}
+
+impl Error {
+    pub fn backend(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Error::Backend(Arc::from(err.into()))
+    }
+
+    /// Whether the server answered that the requested key does not exist.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Refused(Refusal::NotFound { .. }))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Decode(err) => write!(f, "failed to decode message: {}", err),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            Error::Encode(err) => write!(f, "failed to encode message: {}", err),
+            Error::InsufficientPeers => f.write_str("no peers to publish to"),
+            Error::UnknownCorrelation(id) => write!(f, "no pending request with id {}", id),
+            Error::Backend(err) => write!(f, "backend error: {}", err),
+            Error::Closed => f.write_str("client is no longer running"),
+            Error::Timeout { key, attempts } => {
+                write!(f, "no reply for `{}` after {} attempt(s)", key, attempts)
+            }
+            Error::Cancelled => f.write_str("request was cancelled"),
+            Error::Refused(refusal) => write!(f, "request refused: {}", refusal),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Backend(err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(err: rmp_serde::decode::Error) -> Self {
+        Error::Decode(err.to_string())
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(err: rmp_serde::encode::Error) -> Self {
+        Error::Encode(err.to_string())
+    }
+}
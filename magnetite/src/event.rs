@@ -0,0 +1,16 @@
+use crate::{Error, PeerId};
+
+/// Things happening inside a running client or service that are worth
+/// reporting, but don't stop it.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// Processing a message from `source`, or delivering one to it, failed,
+    /// the message was dropped.
+    Error {
+        source: Option<PeerId>,
+        error: Error,
+    },
+    PeerConnected(PeerId),
+    PeerDisconnected(PeerId),
+    Listening(String),
+}
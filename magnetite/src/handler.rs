@@ -0,0 +1,220 @@
+use std::sync::{Mutex, MutexGuard};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{
+    Codec, Envelope, Format, Message, MsgType, PeerId, Refusal, RequestId, Result, Topics,
+};
+
+/// Server side message processing, run by a [`Service`](crate::Service).
+///
+/// Handlers are called for every message received on the topics the service
+/// is subscribed to, and queue their replies on the [`Context`]. An error
+/// drops the message and is reported as an [`Event`](crate::Event), the
+/// service keeps running.
+pub trait Handler: Send + 'static {
+    fn handle(&mut self, message: &Message, ctx: &mut Context) -> Result<()>;
+
+    /// Topics the service subscribes to for this handler, on top of
+    /// [`Topics::request`].
+    fn subscriptions(&self, _topics: &Topics) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Called every [`ServiceBuilder::tick`](crate::ServiceBuilder::tick),
+    /// for work that is not triggered by a message.
+    fn tick(&mut self, _ctx: &mut Context) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the backend loses `peer`, to drop any state kept for it.
+    fn disconnected(&mut self, _peer: &PeerId) {}
+}
+
+/// Locks state shared by clones, such as the clones of a handler, even if
+/// another holder panicked: the state is only ever changed in steps that
+/// leave it consistent, so a panic can't leave it half updated.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Where a message came from, and what should be sent in return.
+pub struct Context {
+    local: PeerId,
+    source: PeerId,
+    sender: PeerId,
+    topic: String,
+    reply_to: Option<String>,
+    format: Format,
+    keyspace: Option<String>,
+    /// The message was sent to this service alone by its sender.
+    direct: bool,
+    topics: Topics,
+    outgoing: Vec<Outgoing>,
+}
+
+pub(crate) struct Outgoing {
+    pub topic: String,
+    /// Peer to send the envelope to alone, rather than publishing it.
+    pub to: Option<PeerId>,
+    pub envelope: Envelope,
+}
+
+impl Context {
+    pub(crate) fn new(
+        local: PeerId,
+        source: PeerId,
+        topic: String,
+        envelope: &Envelope,
+        topics: Topics,
+    ) -> Self {
+        // relayed messages come in on the direct topic as well, from the relay
+        let direct = topic == topics.direct(&local) && source == envelope.sender;
+        Context {
+            local,
+            source,
+            sender: envelope.sender.clone(),
+            topic,
+            reply_to: envelope.reply_to.clone(),
+            format: Format::from_content_type(&envelope.content_type).unwrap_or(topics.codec()),
+            keyspace: envelope.keyspace.clone(),
+            direct,
+            topics,
+            outgoing: Vec::new(),
+        }
+    }
+
+    /// Context of a [`Handler::tick`], there is no message to reply to.
+    pub(crate) fn tick(local: PeerId, topics: Topics) -> Self {
+        Context {
+            source: local.clone(),
+            sender: local.clone(),
+            local,
+            topic: String::new(),
+            reply_to: None,
+            format: topics.codec(),
+            keyspace: None,
+            direct: false,
+            topics,
+            outgoing: Vec::new(),
+        }
+    }
+
+    /// Peer the service runs as.
+    pub fn local(&self) -> &PeerId {
+        &self.local
+    }
+
+    /// Peer the message was received from.
+    pub fn source(&self) -> &PeerId {
+        &self.source
+    }
+
+    /// Peer that created the message, differs from [`Context::source`] when
+    /// the message was forwarded.
+    pub fn sender(&self) -> &PeerId {
+        &self.sender
+    }
+
+    /// Topic the message was received on.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Topics of the service handling the message.
+    pub fn topics(&self) -> &Topics {
+        &self.topics
+    }
+
+    /// Format of the message's payload, replies are expected in the same one.
+    ///
+    /// Payloads in a content type this build has no codec for fall back to
+    /// the format of the service's topics.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Keyspace the request's keys live in, `None` for the default one.
+    pub fn keyspace(&self) -> Option<&str> {
+        self.keyspace.as_deref()
+    }
+
+    /// Sends `message` to the topic the requester asked replies to go to,
+    /// or to the reply topic of the peer `message.id` belongs to. Requests
+    /// the sender sent to this service alone are answered the same way, see
+    /// [`Backend::send`](crate::Backend::send).
+    ///
+    /// The payload must be encoded with [`Context::format`].
+    pub fn reply(&mut self, message: Message) {
+        let topic = self.reply_topic(&message.id);
+        let envelope =
+            Envelope::new(self.local.clone(), message).content_type(self.format.content_type());
+        self.outgoing.push(Outgoing {
+            topic,
+            to: self.direct.then(|| self.sender.clone()),
+            envelope,
+        });
+    }
+
+    /// Decodes the payload of `message`, refusing one that doesn't decode
+    /// as [`Refusal::Invalid`].
+    pub fn decode<T: DeserializeOwned>(&self, message: &Message) -> Result<T, Refusal> {
+        self.format
+            .decode(&message.payload)
+            .map_err(|err| Refusal::Invalid(err.to_string()))
+    }
+
+    /// Replies to `message` with the outcome of the request it is, as a
+    /// [`MsgType::Notification`] with the same id.
+    pub fn reply_result<T: Serialize>(
+        &mut self,
+        message: &Message,
+        reply: &Result<T, Refusal>,
+    ) -> Result<()> {
+        let payload = self.format.encode(reply)?;
+        self.reply(Message {
+            id: message.id.clone(),
+            msgtype: MsgType::Notification,
+            payload,
+        });
+        Ok(())
+    }
+
+    /// Topic replies to the request `id` go to, can be kept to publish
+    /// more messages to the requester later on.
+    pub fn reply_topic(&self, id: &RequestId) -> String {
+        match &self.reply_to {
+            Some(topic) => topic.clone(),
+            None => self.topics.reply(&id.peer),
+        }
+    }
+
+    /// Sends `message` to `topic`, its payload must be encoded with the
+    /// format of the service's topics.
+    pub fn publish(&mut self, topic: impl Into<String>, message: Message) {
+        let envelope = Envelope::new(self.local.clone(), message)
+            .content_type(self.topics.codec().content_type());
+        self.outgoing.push(Outgoing {
+            topic: topic.into(),
+            to: None,
+            envelope,
+        });
+    }
+
+    /// Sends `envelope` to `topic` as it is, keeping the sender and reply
+    /// topic of whoever created it.
+    pub fn forward(&mut self, topic: impl Into<String>, envelope: Envelope) {
+        self.outgoing.push(Outgoing {
+            topic: topic.into(),
+            to: None,
+            envelope,
+        });
+    }
+
+    pub(crate) fn into_outgoing(self) -> Vec<Outgoing> {
+        self.outgoing
+    }
+}
@@ -0,0 +1,265 @@
+//! Bodies of the key/value requests answered by storage handlers.
+//!
+//! Replies are encoded as a `Result<T, Refusal>`, in the format of the request.
+//! Keys live in the keyspace named by the request's envelope, `Dump` and
+//! `Wipe` requests apply to that whole keyspace.
+//!
+//! A `Set` can give its key a time to live, or attach it to a lease: a
+//! `Grant` request creates a lease with a time to live, `KeepAlive` requests
+//! renew it, and once it runs out or is `Revoke`d its keys are deleted.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{RequestId, Value};
+
+/// Versions are handed out by a storage-wide counter, so every write gives
+/// a key a higher version than it ever had, even across deletes. Version `0`
+/// stands for a key that does not exist.
+pub const ABSENT: u64 = 0;
+
+/// A value, and the version it was written at.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Versioned {
+    pub value: Value,
+    pub version: u64,
+}
+
+/// Body of a `Set` request, the reply carries the version it was written at.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SetRequest {
+    pub key: String,
+    pub value: Value,
+    /// The Set only applies if the key is currently at this version, use
+    /// [`ABSENT`] to only create the key.
+    #[serde(default)]
+    pub expected_version: Option<u64>,
+    /// The key is deleted once this long passed without another `Set`.
+    #[serde(default)]
+    pub ttl: Option<Duration>,
+    /// The key is deleted with the lease, takes precedence over `ttl`.
+    #[serde(default)]
+    pub lease: Option<LeaseId>,
+}
+
+impl SetRequest {
+    pub fn new(key: impl Into<String>, value: impl Into<Value>) -> Self {
+        SetRequest {
+            key: key.into(),
+            value: value.into(),
+            expected_version: None,
+            ttl: None,
+            lease: None,
+        }
+    }
+
+    pub fn expected_version(mut self, version: u64) -> Self {
+        self.expected_version = Some(version);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn lease(mut self, lease: LeaseId) -> Self {
+        self.lease = Some(lease);
+        self
+    }
+}
+
+/// Identifies a lease, it is the id of the `Grant` request that created it,
+/// so every server handling the request agrees on it.
+///
+/// A `Grant` request's body is the lease's time to live, `KeepAlive` and
+/// `Revoke` requests carry the lease id. `Revoke` replies with the number of
+/// keys deleted.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct LeaseId(pub RequestId);
+
+impl fmt::Display for LeaseId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lease {}", self.0)
+    }
+}
+
+/// Body of a `Delete` request, the reply tells whether the key existed.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DeleteRequest {
+    pub key: String,
+    /// The Delete only applies if the key is currently at this version.
+    #[serde(default)]
+    pub expected_version: Option<u64>,
+}
+
+impl DeleteRequest {
+    pub fn new(key: impl Into<String>) -> Self {
+        DeleteRequest {
+            key: key.into(),
+            expected_version: None,
+        }
+    }
+
+    pub fn expected_version(mut self, version: u64) -> Self {
+        self.expected_version = Some(version);
+        self
+    }
+}
+
+/// Body of a `Watch` request.
+///
+/// Once acknowledged, every change the server applies to a watched key is
+/// pushed as a `Notification` carrying a [`ChangeEvent`] and the id of the
+/// `Watch` request, until an `Unwatch` with that id or the watcher
+/// disconnecting. Watches are not persisted, they end with the server.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WatchRequest {
+    pub key: String,
+    /// Watch every key starting with `key` rather than only `key` itself.
+    #[serde(default)]
+    pub prefix: bool,
+}
+
+impl WatchRequest {
+    pub fn key(key: impl Into<String>) -> Self {
+        WatchRequest {
+            key: key.into(),
+            prefix: false,
+        }
+    }
+
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        WatchRequest {
+            key: prefix.into(),
+            prefix: true,
+        }
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        if self.prefix {
+            key.starts_with(&self.key)
+        } else {
+            key == self.key
+        }
+    }
+}
+
+/// A `Set` or `Delete` applied to a watched key.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChangeEvent {
+    pub key: String,
+    /// `None` if the key was created.
+    pub old: Option<Versioned>,
+    /// `None` if the key was deleted.
+    pub new: Option<Versioned>,
+}
+
+/// Page size used when a `List` request does not set a limit.
+pub const DEFAULT_LIST_LIMIT: u32 = 100;
+
+/// Largest page a storage handler returns, whatever the request's limit.
+pub const MAX_LIST_LIMIT: u32 = 1000;
+
+/// Body of a `List` request, keys come back in lexicographic order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ListRequest {
+    /// Only keys starting with this prefix are listed.
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// Listing starts after this key, the [`ListPage::next`] of the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+impl ListRequest {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        ListRequest {
+            prefix: prefix.into(),
+            ..ListRequest::default()
+        }
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.cursor = Some(cursor.into());
+        self
+    }
+}
+
+/// Reply to a `List` request.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ListPage {
+    pub keys: Vec<String>,
+    /// Cursor of the next page, `None` once every key was listed.
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// Reply to a `Dump` request: every entry of the keyspace, as of one
+/// revision.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Dump {
+    pub revision: u64,
+    pub entries: BTreeMap<String, Versioned>,
+}
+
+/// Why a storage handler did not apply, or could not answer, a request.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Refusal {
+    /// The request body could not be decoded.
+    Invalid(String),
+    /// The request relies on something the handler does not implement.
+    Unsupported(String),
+    /// The server failed to store the change.
+    Storage(String),
+    /// There is no value stored under the key.
+    NotFound { key: String },
+    /// The key is not at the version the request expected.
+    Conflict {
+        key: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// The write would take the keyspace over its quota.
+    OverQuota { keyspace: String },
+    /// The lease ran out, was revoked, or never existed.
+    LeaseExpired { lease: LeaseId },
+    /// A relay did not forward the request, its route is over a limit.
+    Limited(String),
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Invalid(reason) => write!(f, "invalid request: {}", reason),
+            Refusal::Unsupported(what) => write!(f, "unsupported: {}", what),
+            Refusal::Storage(err) => write!(f, "storage failure: {}", err),
+            Refusal::NotFound { key } => write!(f, "`{}` not found", key),
+            Refusal::Conflict {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "`{}` is at version {}, expected {}",
+                key, actual, expected
+            ),
+            Refusal::OverQuota { keyspace } => {
+                write!(f, "keyspace `{}` is over its quota", keyspace)
+            }
+            Refusal::LeaseExpired { lease } => write!(f, "{} expired", lease),
+            Refusal::Limited(limit) => write!(f, "not relayed: {}", limit),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Codec, Error, PeerId, RequestId, Result};
+
+/// Version of the wire format written by this build.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Oldest wire format this build still understands.
+///
+/// Envelopes from newer versions are always accepted: fields they add are
+/// skipped when decoding, message types they add decode as
+/// [`MsgType::Other`].
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Content types of envelope payloads.
+pub mod content_type {
+    pub const OCTET_STREAM: &str = "application/octet-stream";
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum MsgType {
+    Control,
+    Notification,
+    Set,
+    Get,
+    Delete,
+    Exists,
+    List,
+    Watch,
+    Unwatch,
+    Dump,
+    Wipe,
+    Grant,
+    KeepAlive,
+    Revoke,
+    Announce,
+    Withdraw,
+    Resolve,
+    Subscribe,
+    Forward,
+    /// A message type this build does not know, sent by a newer peer.
+    Other(String),
+}
+
+impl From<String> for MsgType {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "Control" => MsgType::Control,
+            "Notification" => MsgType::Notification,
+            "Set" => MsgType::Set,
+            "Get" => MsgType::Get,
+            "Delete" => MsgType::Delete,
+            "Exists" => MsgType::Exists,
+            "List" => MsgType::List,
+            "Watch" => MsgType::Watch,
+            "Unwatch" => MsgType::Unwatch,
+            "Dump" => MsgType::Dump,
+            "Wipe" => MsgType::Wipe,
+            "Grant" => MsgType::Grant,
+            "KeepAlive" => MsgType::KeepAlive,
+            "Revoke" => MsgType::Revoke,
+            "Announce" => MsgType::Announce,
+            "Withdraw" => MsgType::Withdraw,
+            "Resolve" => MsgType::Resolve,
+            "Subscribe" => MsgType::Subscribe,
+            "Forward" => MsgType::Forward,
+            _ => MsgType::Other(name),
+        }
+    }
+}
+
+impl From<MsgType> for String {
+    fn from(msgtype: MsgType) -> Self {
+        match msgtype {
+            MsgType::Control => "Control".into(),
+            MsgType::Notification => "Notification".into(),
+            MsgType::Set => "Set".into(),
+            MsgType::Get => "Get".into(),
+            MsgType::Delete => "Delete".into(),
+            MsgType::Exists => "Exists".into(),
+            MsgType::List => "List".into(),
+            MsgType::Watch => "Watch".into(),
+            MsgType::Unwatch => "Unwatch".into(),
+            MsgType::Dump => "Dump".into(),
+            MsgType::Wipe => "Wipe".into(),
+            MsgType::Grant => "Grant".into(),
+            MsgType::KeepAlive => "KeepAlive".into(),
+            MsgType::Revoke => "Revoke".into(),
+            MsgType::Announce => "Announce".into(),
+            MsgType::Withdraw => "Withdraw".into(),
+            MsgType::Resolve => "Resolve".into(),
+            MsgType::Subscribe => "Subscribe".into(),
+            MsgType::Forward => "Forward".into(),
+            MsgType::Other(name) => name,
+        }
+    }
+}
+
+/// A request or a reply, as seen by clients and handlers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub id: RequestId,
+    pub msgtype: MsgType,
+    pub payload: Vec<u8>,
+}
+
+/// What actually goes over the wire: a [`Message`] plus routing metadata.
+///
+/// Envelopes are encoded with the [`Codec`] declared for the topic. With the
+/// self-describing formats fields are keyed by name, so they can be added in
+/// later protocol versions without breaking older peers.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Envelope {
+    pub version: u16,
+    /// Peer that created the envelope, not necessarily the one it was
+    /// received from.
+    pub sender: PeerId,
+    /// Id of the request this envelope is, or answers.
+    pub correlation: RequestId,
+    /// Topic replies should be published on.
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// Keyspace the request's keys live in, `None` for the default one.
+    #[serde(default)]
+    pub keyspace: Option<String>,
+    /// Milliseconds since the UNIX epoch at which the envelope was created.
+    #[serde(default)]
+    pub timestamp: u64,
+    pub msgtype: MsgType,
+    #[serde(with = "payload")]
+    pub payload: Vec<u8>,
+}
+
+fn default_content_type() -> String {
+    content_type::OCTET_STREAM.into()
+}
+
+impl Envelope {
+    pub fn new(sender: PeerId, message: Message) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_default();
+        Envelope {
+            version: PROTOCOL_VERSION,
+            sender,
+            correlation: message.id,
+            reply_to: None,
+            content_type: default_content_type(),
+            keyspace: None,
+            timestamp,
+            msgtype: message.msgtype,
+            payload: message.payload,
+        }
+    }
+
+    pub fn reply_to(mut self, topic: impl Into<String>) -> Self {
+        self.reply_to = Some(topic.into());
+        self
+    }
+
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+
+    pub fn keyspace(mut self, keyspace: Option<String>) -> Self {
+        self.keyspace = keyspace;
+        self
+    }
+
+    pub fn message(&self) -> Message {
+        Message {
+            id: self.correlation.clone(),
+            msgtype: self.msgtype.clone(),
+            payload: self.payload.clone(),
+        }
+    }
+
+    pub fn into_message(self) -> Message {
+        Message {
+            id: self.correlation,
+            msgtype: self.msgtype,
+            payload: self.payload,
+        }
+    }
+
+    pub fn encode(&self, codec: &impl Codec) -> Result<Vec<u8>> {
+        codec.encode(self)
+    }
+
+    pub fn decode(codec: &impl Codec, data: &[u8]) -> Result<Self> {
+        let envelope: Envelope = codec.decode(data)?;
+        if envelope.version < MIN_PROTOCOL_VERSION {
+            return Err(Error::UnsupportedVersion(envelope.version));
+        }
+        Ok(envelope)
+    }
+}
+
+/// Payloads are raw bytes in the binary formats, and plain strings in the
+/// human readable ones whenever they are valid UTF-8.
+mod payload {
+    use std::fmt;
+
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(payload: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(payload) {
+            Ok(text) if serializer.is_human_readable() => serializer.serialize_str(text),
+            _ => serializer.serialize_bytes(payload),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(PayloadVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(PayloadVisitor)
+        }
+    }
+
+    struct PayloadVisitor;
+
+    impl<'de> Visitor<'de> for PayloadVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a byte array or a string")
+        }
+
+        fn visit_str<E: de::Error>(self, text: &str) -> Result<Vec<u8>, E> {
+            Ok(text.as_bytes().to_vec())
+        }
+
+        fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
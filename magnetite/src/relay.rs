@@ -0,0 +1,42 @@
+//! Bodies of the requests answered by relay handlers.
+//!
+//! A `Forward` request carries the envelope of another request, meant for a
+//! single peer. The relay publishes it as it is on that peer's
+//! [direct topic](crate::Topics::direct), so the peer replies to the
+//! original requester. Relays only answer, with the id of the carried
+//! request, when they refuse to forward it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Envelope, PeerId};
+
+/// Where a relayed request goes, see [`Client::routed`](crate::Client::routed).
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Route {
+    /// Relay that forwards the request, any relay that sees it when `None`,
+    /// which delivers it once per relay.
+    pub relay: Option<PeerId>,
+    pub to: PeerId,
+}
+
+impl Route {
+    pub fn to(peer: PeerId) -> Self {
+        Route {
+            relay: None,
+            to: peer,
+        }
+    }
+
+    pub fn via(mut self, relay: PeerId) -> Self {
+        self.relay = Some(relay);
+        self
+    }
+}
+
+/// Body of a `Forward` request.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Forward {
+    pub route: Route,
+    /// Request to forward, its sender must be the peer sending the `Forward`.
+    pub envelope: Envelope,
+}
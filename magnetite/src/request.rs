@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::PeerId;
+
+/// Identifies a request on the whole mesh.
+///
+/// The requesting peer is part of the id, so ids minted by different peers
+/// never collide and replies can be addressed back to the requester.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct RequestId {
+    pub peer: PeerId,
+    pub nonce: u64,
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.peer, self.nonce)
+    }
+}
+
+/// Mints [`RequestId`]s for one peer.
+#[derive(Debug)]
+pub struct RequestIds {
+    peer: PeerId,
+    next: u64,
+}
+
+impl RequestIds {
+    /// The counter starts at the current time, so a peer restarted with the
+    /// same identity does not hand out ids still in flight from its last run.
+    pub fn new(peer: PeerId) -> Self {
+        let next = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        RequestIds { peer, next }
+    }
+
+    pub fn peer(&self) -> &PeerId {
+        &self.peer
+    }
+
+    pub fn next_id(&mut self) -> RequestId {
+        let nonce = self.next;
+        self.next = self.next.wrapping_add(1);
+        RequestId {
+            peer: self.peer.clone(),
+            nonce,
+        }
+    }
+}
+
+/// Requests of the local peer that are waiting for a reply.
+#[derive(Debug)]
+pub struct PendingRequests<T> {
+    local: PeerId,
+    pending: HashMap<RequestId, T>,
+}
+
+impl<T> PendingRequests<T> {
+    pub fn new(local: PeerId) -> Self {
+        PendingRequests {
+            local,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, id: RequestId, request: T) {
+        self.pending.insert(id, request);
+    }
+
+    pub fn get(&self, id: &RequestId) -> Option<&T> {
+        self.pending.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &RequestId) -> Option<&mut T> {
+        self.pending.get_mut(id)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.pending.values()
+    }
+
+    /// Takes the request a reply with `id` belongs to.
+    ///
+    /// Replies to other peers' requests, and to requests that were already
+    /// answered or dropped, yield `None`.
+    pub fn resolve(&mut self, id: &RequestId) -> Option<T> {
+        if id.peer != self.local {
+            return None;
+        }
+        self.pending.remove(id)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&RequestId, &mut T) -> bool) {
+        self.pending.retain(|id, request| keep(id, request));
+    }
+
+    pub fn ids(&self) -> Vec<RequestId> {
+        let mut ids: Vec<RequestId> = self.pending.keys().cloned().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+/// How long and how often a client keeps asking before giving up on a request.
+///
+/// After the `n`-th publish of a request the client waits
+/// `initial_backoff * multiplier^(n - 1)`, capped at `max_backoff`, before
+/// publishing it again. The request fails once `timeout` has passed since it
+/// was made, or once `max_attempts` publishes went unanswered. A timeout too
+/// long to tell is no timeout, and clients wait a day at most between two
+/// publishes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub timeout: Option<Duration>,
+    pub max_attempts: Option<u32>,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Some(Duration::from_secs(30)),
+            max_attempts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Keeps asking forever, every `interval`.
+    pub fn forever(interval: Duration) -> Self {
+        RetryPolicy {
+            timeout: None,
+            max_attempts: None,
+            initial_backoff: interval,
+            max_backoff: interval,
+            multiplier: 1,
+        }
+    }
+
+    /// Delay between the `attempt`-th publish of a request and the next one.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .checked_pow(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, warn};
+
+use crate::handler::{Context, Outgoing};
+use crate::{Backend, BackendEvent, Envelope, Error, Event, Handler, PeerId, Result, Topics};
+
+pub struct ServiceBuilder {
+    topics: Topics,
+    handlers: Vec<Box<dyn Handler>>,
+    tick: Duration,
+}
+
+impl Default for ServiceBuilder {
+    fn default() -> Self {
+        ServiceBuilder {
+            topics: Topics::default(),
+            handlers: Vec::new(),
+            tick: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ServiceBuilder {
+    /// Requests are received on `topics.request()`, must match the clients' topics.
+    pub fn topics(mut self, topics: Topics) -> Self {
+        self.topics = topics;
+        self
+    }
+
+    /// Adds a handler, every handler sees every received message.
+    pub fn handler<H: Handler>(mut self, handler: H) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+
+    /// How often [`Handler::tick`] is called, every second by default.
+    pub fn tick(mut self, interval: Duration) -> Self {
+        self.tick = interval;
+        self
+    }
+
+    pub fn build<B: Backend>(self, backend: B) -> Service<B> {
+        let (events, _) = broadcast::channel(64);
+        Service {
+            backend,
+            topics: self.topics,
+            handlers: self.handlers,
+            tick: self.tick,
+            events,
+        }
+    }
+}
+
+/// Runs a set of [`Handler`]s on a backend.
+pub struct Service<B> {
+    backend: B,
+    topics: Topics,
+    handlers: Vec<Box<dyn Handler>>,
+    tick: Duration,
+    events: broadcast::Sender<Event>,
+}
+
+impl Service<()> {
+    pub fn builder() -> ServiceBuilder {
+        ServiceBuilder::default()
+    }
+}
+
+impl<B: Backend> Service<B> {
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Subscribes to the service's events, only events sent after this call
+    /// are received.
+    pub fn events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Runs the service on the current tokio runtime.
+    pub fn spawn(self) -> JoinHandle<Result<()>> {
+        tokio::spawn(self.run())
+    }
+
+    /// Processes messages until the backend shuts down.
+    ///
+    /// Only failing to subscribe stops the service, errors caused by single
+    /// messages are reported as [`Event::Error`].
+    pub async fn run(mut self) -> Result<()> {
+        self.backend.subscribe(&self.topics.request()).await?;
+        let direct = self.topics.direct(&self.backend.local_peer_id());
+        self.backend.subscribe(&direct).await?;
+        let subscriptions: Vec<String> = self
+            .handlers
+            .iter()
+            .flat_map(|handler| handler.subscriptions(&self.topics))
+            .collect();
+        for topic in subscriptions {
+            self.backend.subscribe(&topic).await?;
+        }
+
+        let mut ticks = tokio::time::interval(self.tick);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            let event = tokio::select! {
+                _ = ticks.tick() => {
+                    self.tick().await;
+                    continue;
+                }
+                event = self.backend.next_event() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+            };
+            match event {
+                BackendEvent::Message {
+                    source,
+                    topic,
+                    data,
+                } => {
+                    if let Err(error) = self.process(source.clone(), topic, &data).await {
+                        self.report(Some(source), error);
+                    }
+                }
+                BackendEvent::PeerConnected(peer) => self.emit(Event::PeerConnected(peer)),
+                BackendEvent::PeerDisconnected(peer) => {
+                    for handler in self.handlers.iter_mut() {
+                        handler.disconnected(&peer);
+                    }
+                    self.emit(Event::PeerDisconnected(peer));
+                }
+                BackendEvent::Listening(addr) => {
+                    debug!("listening on {}", addr);
+                    self.emit(Event::Listening(addr));
+                }
+                BackendEvent::Failed { peer, error } => {
+                    self.report(Some(peer), Error::backend(error))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn process(&mut self, source: PeerId, topic: String, data: &[u8]) -> Result<()> {
+        let envelope = Envelope::decode(&self.topics.codec(), data)?;
+        let message = envelope.message();
+        let mut ctx = Context::new(
+            self.backend.local_peer_id(),
+            source.clone(),
+            topic,
+            &envelope,
+            self.topics.clone(),
+        );
+        let errors: Vec<Error> = self
+            .handlers
+            .iter_mut()
+            .filter_map(|handler| handler.handle(&message, &mut ctx).err())
+            .collect();
+        for error in errors {
+            self.report(Some(source.clone()), error);
+        }
+        self.flush(ctx).await;
+        Ok(())
+    }
+
+    async fn tick(&mut self) {
+        let mut ctx = Context::tick(self.backend.local_peer_id(), self.topics.clone());
+        let errors: Vec<Error> = self
+            .handlers
+            .iter_mut()
+            .filter_map(|handler| handler.tick(&mut ctx).err())
+            .collect();
+        for error in errors {
+            self.report(None, error);
+        }
+        self.flush(ctx).await;
+    }
+
+    /// Publishes what the handlers queued on `ctx`.
+    async fn flush(&mut self, ctx: Context) {
+        for outgoing in ctx.into_outgoing() {
+            let Outgoing {
+                topic,
+                to,
+                envelope,
+            } = outgoing;
+            let published = match (envelope.encode(&self.topics.codec()), to) {
+                (Ok(data), Some(peer)) => self.backend.send(&peer, &topic, data).await,
+                (Ok(data), None) => self.backend.publish(&topic, data).await,
+                (Err(error), _) => Err(error),
+            };
+            match published {
+                Ok(()) => {}
+                // e.g. a lone server announcing itself
+                Err(Error::InsufficientPeers) => debug!("nobody subscribed to {}", topic),
+                Err(error) => self.report(None, error),
+            }
+        }
+    }
+
+    fn report(&self, source: Option<PeerId>, error: Error) {
+        warn!("dropped message: {}", error);
+        self.emit(Event::Error { source, error });
+    }
+
+    fn emit(&self, event: Event) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+}
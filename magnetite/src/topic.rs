@@ -0,0 +1,96 @@
+use crate::{Format, PeerId};
+
+/// Where replies to a service's requests are published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplyTopic {
+    /// All clients of the service share one reply topic, and drop the
+    /// replies to requests they did not make.
+    Shared,
+    /// Every client listens on a reply topic of its own, derived from its peer id.
+    PerClient,
+}
+
+/// Naming scheme of the topics a service and its clients talk on.
+///
+/// Both sides derive their subscriptions and publish targets from the same
+/// value, so as long as they are configured with equal `Topics` requests and
+/// replies always meet:
+///
+/// * requests: `{namespace}/{service}/request`
+/// * shared replies: `{namespace}/{service}/reply`
+/// * per-client replies: `{namespace}/{service}/reply/{peer id}`
+/// * replication between servers: `{namespace}/{service}/replication`
+/// * messages relayed to one peer: `{namespace}/{service}/direct/{peer id}`
+///
+/// The same goes for the [`Format`] envelopes on these topics are encoded with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Topics {
+    namespace: String,
+    service: String,
+    replies: ReplyTopic,
+    format: Format,
+}
+
+impl Default for Topics {
+    fn default() -> Self {
+        Topics::new("default")
+    }
+}
+
+impl Topics {
+    pub fn new(service: impl Into<String>) -> Self {
+        Topics {
+            namespace: "magnetite".into(),
+            service: service.into(),
+            replies: ReplyTopic::PerClient,
+            format: Format::default(),
+        }
+    }
+
+    /// Prefix of every topic, keeps unrelated deployments on one mesh apart.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    pub fn replies(mut self, replies: ReplyTopic) -> Self {
+        self.replies = replies;
+        self
+    }
+
+    /// Format of the envelopes, and of the payloads of requests.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn codec(&self) -> Format {
+        self.format
+    }
+
+    /// Topic the service subscribes to and clients publish requests on.
+    pub fn request(&self) -> String {
+        format!("{}/{}/request", self.namespace, self.service)
+    }
+
+    /// Topic the servers of the service talk to each other on.
+    pub fn replication(&self) -> String {
+        format!("{}/{}/replication", self.namespace, self.service)
+    }
+
+    /// Topic a service subscribes to for the requests relayed to it alone,
+    /// see [`relay`](crate::relay).
+    pub fn direct(&self, peer: &PeerId) -> String {
+        format!("{}/{}/direct/{}", self.namespace, self.service, peer)
+    }
+
+    /// Topic the reply to a request from `client` is published on.
+    pub fn reply(&self, client: &PeerId) -> String {
+        match self.replies {
+            ReplyTopic::Shared => format!("{}/{}/reply", self.namespace, self.service),
+            ReplyTopic::PerClient => {
+                format!("{}/{}/reply/{}", self.namespace, self.service, client)
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Stored values: raw bytes, tagged with what they hold.
+
+use std::convert::TryInto;
+use std::fmt;
+
+use serde::de::value::{BoolDeserializer, I64Deserializer, SeqDeserializer, StrDeserializer};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// What the bytes of a [`Value`] hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Kind {
+    /// UTF-8 text.
+    String,
+    /// A signed 64 bit integer, big endian.
+    Int,
+    /// A single `0` or `1` byte.
+    Bool,
+    Bytes,
+    /// A UTF-8 JSON document.
+    Json,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::String => "string",
+            Kind::Int => "int",
+            Kind::Bool => "bool",
+            Kind::Bytes => "bytes",
+            Kind::Json => "JSON",
+        })
+    }
+}
+
+/// A value as stored and sent over the wire.
+///
+/// Values convert from the types they can hold, so `client.set(key, 42)`
+/// stores an [`Kind::Int`]. [`Value::decode`] turns them into any type that
+/// deserializes from what they hold.
+#[derive(Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Value {
+    kind: Kind,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+impl Value {
+    /// A value of `kind` made of `data`, which is not checked to match it.
+    pub fn new(kind: Kind, data: Vec<u8>) -> Self {
+        Value { kind, data }
+    }
+
+    /// Serializes `value` as a JSON document.
+    #[cfg(feature = "json")]
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Result<Self> {
+        let data = serde_json::to_vec(value).map_err(|err| Error::Encode(err.to_string()))?;
+        Ok(Value::new(Kind::Json, data))
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// The text of a string or JSON value.
+    pub fn as_str(&self) -> Option<&str> {
+        match self.kind {
+            Kind::String | Kind::Json => std::str::from_utf8(&self.data).ok(),
+            Kind::Int | Kind::Bool | Kind::Bytes => None,
+        }
+    }
+
+    /// The value written out as text, `None` for bytes.
+    pub fn to_text(&self) -> Option<String> {
+        match self.kind {
+            Kind::String | Kind::Json => self.as_str().map(str::to_owned),
+            Kind::Int => self.int().ok().map(|int| int.to_string()),
+            Kind::Bool => self.bool().ok().map(|bool| bool.to_string()),
+            Kind::Bytes => None,
+        }
+    }
+
+    /// Deserializes the value into `T`, which must accept what the value
+    /// holds: a string, an `i64`, a `bool`, a sequence of bytes, or the
+    /// JSON document (with the `json` feature).
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T> {
+        let decoded = match self.kind {
+            Kind::String => {
+                let text = self.as_str().ok_or_else(|| self.malformed())?;
+                T::deserialize(StrDeserializer::new(text))
+            }
+            Kind::Int => T::deserialize(I64Deserializer::new(self.int()?)),
+            Kind::Bool => T::deserialize(BoolDeserializer::new(self.bool()?)),
+            Kind::Bytes => T::deserialize(SeqDeserializer::new(self.data.iter().copied())),
+            #[cfg(feature = "json")]
+            Kind::Json => {
+                return serde_json::from_slice(&self.data)
+                    .map_err(|err| Error::Decode(err.to_string()))
+            }
+            #[cfg(not(feature = "json"))]
+            Kind::Json => {
+                return Err(Error::Decode(
+                    "JSON values need the `json` feature".to_owned(),
+                ))
+            }
+        };
+        decoded.map_err(|err: serde::de::value::Error| {
+            Error::Decode(format!("{} value: {}", self.kind, err))
+        })
+    }
+
+    fn int(&self) -> Result<i64> {
+        let bytes = self
+            .data
+            .as_slice()
+            .try_into()
+            .map_err(|_| self.malformed())?;
+        Ok(i64::from_be_bytes(bytes))
+    }
+
+    fn bool(&self) -> Result<bool> {
+        match self.data.as_slice() {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(self.malformed()),
+        }
+    }
+
+    /// The value as text, failing for bytes.
+    pub(crate) fn into_text(self, key: &str) -> Result<String> {
+        self.to_text()
+            .ok_or_else(|| Error::Decode(format!("`{}` holds {}, not text", key, self.kind)))
+    }
+
+    fn malformed(&self) -> Error {
+        Error::Decode(format!("malformed {} value", self.kind))
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_text() {
+            Some(text) => write!(f, "{}({:?})", self.kind, text),
+            None => write!(f, "{}({} bytes)", self.kind, self.data.len()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_text() {
+            Some(text) => f.write_str(&text),
+            None => write!(f, "<{} bytes>", self.data.len()),
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::new(Kind::String, text.into_bytes())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::from(text.to_owned())
+    }
+}
+
+impl From<&String> for Value {
+    fn from(text: &String) -> Self {
+        Value::from(text.as_str())
+    }
+}
+
+macro_rules! from_int {
+    ($($int:ty),*) => {$(
+        impl From<$int> for Value {
+            fn from(int: $int) -> Self {
+                Value::new(Kind::Int, i64::from(int).to_be_bytes().to_vec())
+            }
+        }
+    )*};
+}
+
+from_int!(i8, i16, i32, i64, u8, u16, u32);
+
+impl From<bool> for Value {
+    fn from(bool: bool) -> Self {
+        Value::new(Kind::Bool, vec![bool as u8])
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::new(Kind::Bytes, bytes)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value::from(bytes.to_vec())
+    }
+}
+
+/// Only string values equal text.
+impl PartialEq<str> for Value {
+    fn eq(&self, text: &str) -> bool {
+        self.kind == Kind::String && self.data == text.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for Value {
+    fn eq(&self, text: &&str) -> bool {
+        self == *text
+    }
+}
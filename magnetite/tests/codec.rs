@@ -0,0 +1,126 @@
+//! Every format compiled in is covered, run with `--all-features` to test
+//! them all.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use magnetite::request::RequestIds;
+use magnetite::{
+    ChangeEvent, Codec, DeleteRequest, Dump, Envelope, Format, Kind, LeaseId, ListPage,
+    ListRequest, Message, MsgType, PeerId, Refusal, SetRequest, Value, Versioned, WatchRequest,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: &T) {
+    for format in Format::ALL {
+        let data = format.encode(value).unwrap();
+        let decoded: T = format
+            .decode(&data)
+            .unwrap_or_else(|err| panic!("{:?} failed to decode {:?}: {}", format, value, err));
+        assert_eq!(&decoded, value, "{:?}", format);
+    }
+}
+
+fn versioned(value: impl Into<Value>, version: u64) -> Versioned {
+    Versioned {
+        value: value.into(),
+        version,
+    }
+}
+
+#[test]
+fn envelopes_round_trip() {
+    let mut ids = RequestIds::new(PeerId::new("client"));
+    for payload in [
+        b"configservice.port".to_vec(),
+        vec![0, 159, 146, 150],
+        vec![],
+    ] {
+        let message = Message {
+            id: ids.next_id(),
+            msgtype: MsgType::Get,
+            payload,
+        };
+        round_trip(&Envelope::new(PeerId::new("client"), message.clone()));
+        round_trip(
+            &Envelope::new(PeerId::new("client"), message)
+                .reply_to("magnetite/default/reply/client")
+                .content_type(Format::MessagePack.content_type())
+                .keyspace(Some("tenant-a".into())),
+        );
+    }
+}
+
+#[test]
+fn requests_round_trip() {
+    let lease = LeaseId(RequestIds::new(PeerId::new("client")).next_id());
+
+    round_trip(&SetRequest::new("configservice.port", 61250));
+    round_trip(
+        &SetRequest::new("configservice.user", "public")
+            .expected_version(3)
+            .ttl(Duration::from_millis(1500))
+            .lease(lease),
+    );
+    round_trip(&DeleteRequest::new("configservice.user").expected_version(4));
+    round_trip(&WatchRequest::prefix("configservice."));
+    round_trip(
+        &ListRequest::new("configservice.")
+            .limit(10)
+            .cursor("configservice.port"),
+    );
+    round_trip(&Duration::from_secs(30));
+}
+
+#[test]
+fn replies_round_trip() {
+    let reply: Result<Versioned, Refusal> = Ok(versioned("localhost", 7));
+    round_trip(&reply);
+    round_trip(&Ok::<_, Refusal>(versioned(vec![0u8, 255], 1)));
+    round_trip(&Ok::<_, Refusal>(versioned(true, 2)));
+    round_trip(&Ok::<_, Refusal>(versioned(
+        Value::new(Kind::Json, b"{}".to_vec()),
+        3,
+    )));
+    round_trip(&Ok::<u64, Refusal>(12));
+    round_trip(&Ok::<(), Refusal>(()));
+    round_trip(&ListPage {
+        keys: vec!["configservice.address".into(), "configservice.port".into()],
+        next: Some("configservice.port".into()),
+    });
+    let mut entries = BTreeMap::new();
+    entries.insert("configservice.port".to_owned(), versioned(61250, 5));
+    round_trip(&Dump {
+        revision: 5,
+        entries,
+    });
+    round_trip(&ChangeEvent {
+        key: "configservice.port".into(),
+        old: Some(versioned(61250, 5)),
+        new: None,
+    });
+}
+
+#[test]
+fn refusals_round_trip() {
+    let refusals = [
+        Refusal::Invalid("truncated".into()),
+        Refusal::NotFound {
+            key: "configservice.port".into(),
+        },
+        Refusal::Conflict {
+            key: "configservice.port".into(),
+            expected: 3,
+            actual: 4,
+        },
+        Refusal::OverQuota {
+            keyspace: "tenant-a".into(),
+        },
+        Refusal::Limited("too many requests".into()),
+    ];
+    for refusal in refusals {
+        round_trip(&Err::<u64, _>(refusal));
+    }
+}
@@ -0,0 +1,56 @@
+use magnetite::request::RequestIds;
+use magnetite::{Codec, Envelope, Error, Format, Message, MsgType, PeerId, PROTOCOL_VERSION};
+use serde::Serialize;
+
+/// An envelope as a newer peer could send it.
+#[derive(Serialize)]
+struct Newer {
+    #[serde(flatten)]
+    envelope: Envelope,
+    priority: u8,
+}
+
+fn envelope(msgtype: MsgType) -> Envelope {
+    let id = RequestIds::new(PeerId::new("client")).next_id();
+    let message = Message {
+        id,
+        msgtype,
+        payload: b"configservice.port".to_vec(),
+    };
+    Envelope::new(PeerId::new("client"), message).reply_to("magnetite/default/reply/client")
+}
+
+#[test]
+fn fields_added_by_newer_peers_are_skipped() {
+    let sent = envelope(MsgType::Get);
+    let data = Format::MessagePack
+        .encode(&Newer {
+            envelope: sent.clone(),
+            priority: 3,
+        })
+        .unwrap();
+
+    let received = Envelope::decode(&Format::MessagePack, &data).unwrap();
+    assert_eq!(received, sent);
+}
+
+#[test]
+fn message_types_added_by_newer_peers_decode_as_other() {
+    let mut sent = envelope(MsgType::Other("Compact".into()));
+    sent.version = PROTOCOL_VERSION + 1;
+    let data = sent.encode(&Format::MessagePack).unwrap();
+
+    let received = Envelope::decode(&Format::MessagePack, &data).unwrap();
+    assert_eq!(received.msgtype, MsgType::Other("Compact".into()));
+    assert_eq!(received.version, PROTOCOL_VERSION + 1);
+}
+
+#[test]
+fn versions_too_old_are_refused() {
+    let mut sent = envelope(MsgType::Get);
+    sent.version = 0;
+    let data = sent.encode(&Format::MessagePack).unwrap();
+
+    let err = Envelope::decode(&Format::MessagePack, &data).unwrap_err();
+    assert!(matches!(err, Error::UnsupportedVersion(0)), "{:?}", err);
+}
@@ -0,0 +1,74 @@
+use magnetite::request::{PendingRequests, RequestIds};
+use magnetite::{
+    Client, Codec, Context, Event, Handler, Message, MsgType, PeerId, Refusal, ReplyTopic, Result,
+    Service, Topics, Versioned,
+};
+use magnetite_memory::MemoryNetwork;
+
+/// Answers every `Get` with the name of the peer that sent it.
+struct WhoAsked;
+
+impl Handler for WhoAsked {
+    fn handle(&mut self, message: &Message, ctx: &mut Context) -> Result<()> {
+        if message.msgtype != MsgType::Get {
+            return Ok(());
+        }
+        let reply: std::result::Result<Versioned, Refusal> = Ok(Versioned {
+            value: ctx.sender().as_str().into(),
+            version: 1,
+        });
+        let payload = ctx.format().encode(&reply)?;
+        ctx.reply(Message {
+            id: message.id.clone(),
+            msgtype: MsgType::Notification,
+            payload,
+        });
+        Ok(())
+    }
+}
+
+#[test]
+fn replies_to_other_peers_do_not_resolve() {
+    let mut ids = RequestIds::new(PeerId::new("client-a"));
+    let mut pending = PendingRequests::new(PeerId::new("client-a"));
+    let id = ids.next_id();
+    pending.insert(id.clone(), "configservice.port");
+
+    let mut other = id.clone();
+    other.peer = PeerId::new("client-b");
+    assert_eq!(pending.resolve(&other), None);
+    assert_eq!(pending.resolve(&id), Some("configservice.port"));
+    assert_eq!(pending.resolve(&id), None);
+}
+
+#[tokio::test]
+async fn clients_sharing_a_reply_topic_only_take_their_own_replies() {
+    let topics = Topics::default().replies(ReplyTopic::Shared);
+    let network = MemoryNetwork::new();
+    Service::builder()
+        .topics(topics.clone())
+        .handler(WhoAsked)
+        .build(network.join("server"))
+        .spawn();
+    let first = Client::builder()
+        .topics(topics.clone())
+        .build(network.join("client-a"));
+    let second = Client::builder()
+        .topics(topics)
+        .build(network.join("client-b"));
+    let mut events = first.events();
+
+    for _ in 0..3 {
+        let (a, b) = tokio::join!(
+            first.get("configservice.port"),
+            second.get("configservice.port")
+        );
+        assert_eq!(a.unwrap(), "client-a");
+        assert_eq!(b.unwrap(), "client-b");
+    }
+
+    // the replies to client-b reached client-a as well, and were dropped quietly
+    while let Ok(event) = events.try_recv() {
+        assert!(!matches!(event, Event::Error { .. }), "{:?}", event);
+    }
+}
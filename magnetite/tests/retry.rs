@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use magnetite::{Client, Error, RetryPolicy, Service};
+use magnetite_memory::MemoryNetwork;
+use tokio::time::{sleep, timeout};
+
+/// A service without handlers, requests reach it and are never answered.
+fn silent() -> MemoryNetwork {
+    let network = MemoryNetwork::new();
+    Service::builder().build(network.join("server")).spawn();
+    network
+}
+
+#[test]
+fn backoff_grows_up_to_its_cap() {
+    let retry = RetryPolicy::default();
+
+    let delays: Vec<u64> = (1..=5).map(|n| retry.backoff(n).as_secs()).collect();
+    assert_eq!(delays, [1, 2, 4, 8, 10]);
+    assert_eq!(retry.backoff(0), Duration::from_secs(1));
+}
+
+#[test]
+fn backoff_overflow_is_capped() {
+    let retry = RetryPolicy::default();
+    assert_eq!(retry.backoff(u32::MAX), retry.max_backoff);
+
+    let retry = RetryPolicy {
+        initial_backoff: Duration::MAX,
+        max_backoff: Duration::from_secs(60),
+        ..RetryPolicy::default()
+    };
+    assert_eq!(retry.backoff(1), Duration::from_secs(60));
+    assert_eq!(retry.backoff(2), Duration::from_secs(60));
+
+    let retry = RetryPolicy::forever(Duration::from_millis(500));
+    assert_eq!(retry.backoff(1000), Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let retry = RetryPolicy {
+        max_attempts: Some(3),
+        ..RetryPolicy::forever(Duration::from_millis(10))
+    };
+    let client = Client::builder()
+        .retry(retry)
+        .build(silent().join("client"));
+
+    let err = timeout(Duration::from_secs(5), client.get("configservice.port"))
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    match err {
+        Error::Timeout { key, attempts } => {
+            assert_eq!(key, "configservice.port");
+            assert_eq!(attempts, 3);
+        }
+        err => panic!("expected a timeout, got {:?}", err),
+    }
+}
+
+#[tokio::test]
+async fn gives_up_at_the_deadline() {
+    let retry = RetryPolicy {
+        timeout: Some(Duration::from_millis(100)),
+        ..RetryPolicy::forever(Duration::from_millis(10))
+    };
+    let client = Client::builder()
+        .retry(retry)
+        .build(silent().join("client"));
+
+    let err = timeout(Duration::from_secs(5), client.get("configservice.port"))
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    assert!(
+        matches!(err, Error::Timeout { attempts, .. } if attempts > 1),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn huge_timeouts_and_backoffs_wait() {
+    let retry = RetryPolicy {
+        timeout: Some(Duration::MAX),
+        ..RetryPolicy::forever(Duration::MAX)
+    };
+    let client = Client::builder()
+        .retry(retry)
+        .build(silent().join("client"));
+
+    let waiting = timeout(Duration::from_millis(200), client.get("configservice.port")).await;
+    assert!(waiting.is_err(), "{:?}", waiting);
+}
+
+#[tokio::test]
+async fn cancel_fails_outstanding_requests() {
+    let client = Client::builder()
+        .want("configservice.address")
+        .retry(RetryPolicy::forever(Duration::from_millis(10)))
+        .build(silent().join("client"));
+
+    let (got, ()) = tokio::join!(client.get("configservice.port"), async {
+        sleep(Duration::from_millis(50)).await;
+        client.cancel();
+    });
+
+    assert!(matches!(got, Err(Error::Cancelled)), "{:?}", got);
+    assert!(matches!(client.ready().await, Err(Error::Cancelled)));
+}